@@ -0,0 +1,134 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use cortex_m::{iprintln, peripheral};
+extern crate stm32f4xx_hal as hal;
+use bbqueue::{consts::*, BBBuffer, ConstBBBuffer, Consumer, Producer};
+use capture::{Block, FRAME_MAX};
+use hal::{
+    adc::{config::AdcConfig, config::SampleTime, Adc},
+    gpio::{gpioa::PA3, Analog},
+    nb::block,
+    prelude::*,
+    serial::{config::Config, Serial},
+    stm32,
+    stm32::USART3,
+    timer::{Event as TimerEvent, Timer},
+};
+
+/// Sample period in microseconds
+const PERIOD_US: u16 = 500;
+
+#[path = "shared/capture.rs"]
+mod capture;
+
+// Create a buffer with 4096 elements
+static BB: BBBuffer<U4096> = BBBuffer(ConstBBBuffer::new());
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[rtic::app(device = hal::stm32, peripherals = true)]
+const APP: () = {
+    struct Resources {
+        cons: Consumer<'static, U4096>,
+        prod: Producer<'static, U4096>,
+        tx: hal::serial::Tx<USART3>,
+        timer: Timer<stm32::TIM2>,
+        adc: Adc<stm32::ADC1>,
+        pin: PA3<Analog>,
+        block: Block,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> init::LateResources {
+        let rcc = cx.device.RCC.constrain();
+        let clocks = rcc.cfgr.sysclk(84.mhz()).freeze();
+
+        // Split bbqueue Producer and Consumer
+        let (prod, cons) = BB.try_split().unwrap();
+
+        // Set up UART. 921600 bps leaves room for 2 kS/s of 16 bit samples.
+        let gpioc = cx.device.GPIOC.split();
+        let tx = gpioc.pc10.into_alternate_af7();
+        let rx = gpioc.pc11.into_alternate_af7();
+        let serial = Serial::usart3(
+            cx.device.USART3,
+            (tx, rx),
+            Config::default().baudrate(921_600.bps()),
+            clocks,
+        )
+        .unwrap();
+        let (tx, _rx) = serial.split();
+
+        // Set up ADC
+        let gpioa = cx.device.GPIOA.split();
+        let pin = gpioa.pa3.into_analog();
+        let adc = Adc::adc1(cx.device.ADC1, true, AdcConfig::default());
+
+        // Set up the sample timer
+        let mut timer = Timer::tim2(cx.device.TIM2, (1_000_000 / PERIOD_US as u32).hz(), clocks);
+        timer.listen(TimerEvent::TimeOut);
+
+        // Initialization of late resources
+        init::LateResources {
+            cons,
+            prod,
+            tx,
+            timer,
+            adc,
+            pin,
+            block: Block::new(PERIOD_US),
+        }
+    }
+
+    // Timer interrupt, take a sample and queue the block once it is full
+    #[task(binds = TIM2, priority = 2, resources = [timer, adc, pin, block, prod], spawn = [send])]
+    fn tim2(cx: tim2::Context) {
+        cx.resources.timer.clear_interrupt(TimerEvent::TimeOut);
+        let sample = cx
+            .resources
+            .adc
+            .convert(&*cx.resources.pin, SampleTime::Cycles_56);
+        if !cx.resources.block.push(sample) {
+            return;
+        }
+        match cx.resources.prod.grant_exact(FRAME_MAX) {
+            Ok(mut wgr) => {
+                let len = cx.resources.block.encode(&mut wgr);
+                wgr.commit(len);
+                let _ = cx.spawn.send();
+            }
+            // The host is not keeping up, the block is dropped and shows up as a sequence gap
+            Err(_) => iprintln!(itm(), "[CAPTURE] Overrun"),
+        }
+        cx.resources.block.next();
+    }
+
+    // Software task, drain the queue to the TX buffer
+    #[task(resources = [cons, tx])]
+    fn send(cx: send::Context) {
+        while let Ok(rgr) = cx.resources.cons.read() {
+            let len = rgr.len();
+            rgr.buf()
+                .iter()
+                .for_each(|&byte| match block!(cx.resources.tx.write(byte)) {
+                    Ok(_) => (),
+                    Err(error) => {
+                        iprintln!(itm(), "[TX] Err: {:?}", error);
+                    }
+                });
+            // Release the space for later writes
+            rgr.release(len);
+        }
+    }
+
+    // This is required for the software task fn send()
+    // This can be any interrupt not used by hardware
+    extern "C" {
+        fn USART1();
+    }
+};
@@ -0,0 +1,68 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use adc_multi::{MultiAdc, MultiMode};
+use cortex_m::{iprintln, peripheral, singleton};
+use cortex_m_rt::entry;
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, Continuous, SampleTime, Sequence},
+        Adc,
+    },
+    prelude::*,
+    stm32,
+};
+
+const PAIRS: usize = 64;
+
+#[allow(dead_code)]
+#[path = "shared/adc_multi.rs"]
+mod adc_multi;
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let _clocks = rcc.cfgr.freeze();
+    let gpioa = dp.GPIOA.split();
+
+    // Voltage on PA3 (ADC1), current on PA4 (ADC2)
+    let voltage = gpioa.pa3.into_analog();
+    let current = gpioa.pa4.into_analog();
+
+    // ADC1 is the master. Resetting it resets the common registers, so only ADC1 does.
+    let config = || AdcConfig::default().continuous(Continuous::Continuous);
+    let mut adc1 = Adc::adc1(dp.ADC1, true, config());
+    let mut adc2 = Adc::adc2(dp.ADC2, false, config());
+    adc1.configure_channel(&voltage, Sequence::One, SampleTime::Cycles_84);
+    adc2.configure_channel(&current, Sequence::One, SampleTime::Cycles_84);
+
+    // Both ADCs sample at the same instant, the pair is moved as one 32 bit word
+    // ADC1, ADC2 results in turn
+    let buffer = singleton!(: [u16; 2 * PAIRS] = [0; 2 * PAIRS]).unwrap();
+    let multi = MultiAdc::new(dp.ADC_COMMON, MultiMode::DualSimultaneous, buffer);
+    multi.start_dma(&dp.DMA2);
+
+    adc2.enable();
+    adc1.enable();
+    // Starting the master starts the slave
+    adc1.start_conversion();
+
+    loop {
+        let mut samples = [0; 2 * PAIRS];
+        multi.read(&mut samples);
+        // Instantaneous power averaged over the buffer, in raw ADC units
+        let sum: u64 = samples
+            .chunks(2)
+            .map(|pair| pair[0] as u64 * pair[1] as u64)
+            .sum();
+        iprintln!(itm(), "P: {}", sum / PAIRS as u64);
+        cortex_m::asm::delay(16_000_000);
+    }
+}
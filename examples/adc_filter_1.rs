@@ -0,0 +1,136 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::{
+    interrupt::{free, Mutex},
+    singleton, {iprintln, peripheral},
+};
+use cortex_m_rt::entry;
+use filter::{
+    Biquad, BiquadF32, Cascade, CascadeFixed, Filter, Fir, FirF32, Median, MovingAverage,
+    BIQUAD_LOWPASS, BIQUAD_LOWPASS_Q28, FIR_LOWPASS, FIR_LOWPASS_F32,
+};
+use stm32f4xx_hal::{
+    adc::{
+        config::AdcConfig, config::Eoc, config::ExternalTrigger, config::SampleTime,
+        config::Sequence, config::TriggerMode, Adc,
+    },
+    prelude::*,
+    pwm, stm32,
+    stm32::interrupt,
+};
+
+/// Filters applied to every sample
+struct Chain {
+    median: Median<'static, u16>,
+    average: MovingAverage<'static, u16>,
+    fir: Fir<'static, i16>,
+    fir_f32: FirF32<'static>,
+    biquad: Cascade<'static>,
+    biquad_fixed: CascadeFixed<'static, i16>,
+    count: u32,
+}
+
+static ADC: Mutex<RefCell<Option<Adc<stm32::ADC1>>>> = Mutex::new(RefCell::new(None));
+static CHAIN: Mutex<RefCell<Option<Chain>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[interrupt]
+fn ADC() {
+    free(|cs| {
+        if let (Some(ref mut adc), Some(ref mut chain)) = (
+            ADC.borrow(cs).borrow_mut().deref_mut(),
+            CHAIN.borrow(cs).borrow_mut().deref_mut(),
+        ) {
+            // Reading the result from ADC_DR clears the EOC flag automatically.
+            let sample = adc.current_sample();
+            // Remove spikes first, then smooth
+            let despiked = chain.median.process(sample);
+            let average = chain.average.process(despiked);
+            // Center around 0 for the signed filters
+            let centered = despiked as i16 - 0x0800;
+            let fir = chain.fir.process(centered);
+            let fir_f32 = chain.fir_f32.process(centered as f32);
+            let iir = chain.biquad.process(centered as f32);
+            let iir_fixed = chain.biquad_fixed.process(centered);
+
+            chain.count += 1;
+            if chain.count % 1000 == 0 {
+                iprintln!(
+                    itm(),
+                    "raw: {} median: {} avg: {} fir: {} fir (f32): {} iir: {} iir (Q28): {}",
+                    sample,
+                    despiked,
+                    average,
+                    fir,
+                    fir_f32 as i32,
+                    iir as i32,
+                    iir_fixed
+                );
+            }
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let gpioa = dp.GPIOA.split();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.freeze();
+
+    // Filter state lives in statically allocated buffers
+    let chain = Chain {
+        median: Median::new(
+            singleton!(: [u16; 5] = [0; 5]).unwrap(),
+            singleton!(: [u16; 5] = [0; 5]).unwrap(),
+        ),
+        average: MovingAverage::new(singleton!(: [u16; 16] = [0; 16]).unwrap()),
+        fir: Fir::new(&FIR_LOWPASS, singleton!(: [i16; 9] = [0; 9]).unwrap()),
+        fir_f32: FirF32::new(&FIR_LOWPASS_F32, singleton!(: [f32; 9] = [0.0; 9]).unwrap()),
+        biquad: Cascade::new(singleton!(: [BiquadF32; 2] = [BIQUAD_LOWPASS; 2]).unwrap()),
+        biquad_fixed: CascadeFixed::new(
+            singleton!(: [Biquad<i16>; 2] = [BIQUAD_LOWPASS_Q28; 2]).unwrap(),
+        ),
+        count: 0,
+    };
+
+    // 1 kHz sample clock from TIM1 CC1
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let mut pwm = pwm::tim1(dp.TIM1, pa8, clocks, 1.khz());
+    let max_duty = pwm.get_max_duty();
+    pwm.set_duty(max_duty / 2);
+    pwm.enable();
+
+    // Configure ADC
+    let config = AdcConfig::default()
+        .end_of_conversion_interrupt(Eoc::Conversion)
+        .external_trigger(TriggerMode::RisingEdge, ExternalTrigger::Tim_1_cc_1);
+    let mut adc = Adc::adc1(dp.ADC1, true, config);
+    let pa3 = gpioa.pa3.into_analog();
+    adc.configure_channel(&pa3, Sequence::One, SampleTime::Cycles_112);
+    adc.enable();
+
+    // Move the shared resources to Mutex
+    free(|cs| {
+        ADC.borrow(cs).replace(Some(adc));
+        CHAIN.borrow(cs).replace(Some(chain));
+    });
+
+    // Enable interrupt
+    unsafe {
+        stm32::NVIC::unmask(stm32::interrupt::ADC);
+    }
+
+    loop {}
+}
+
+#[path = "shared/filter.rs"]
+mod filter;
@@ -0,0 +1,285 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::{
+    interrupt::{free, Mutex},
+    {iprintln, peripheral, singleton},
+};
+use cortex_m_rt::entry;
+use injected::{Injected, InjectedTrigger, RegularDma};
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, Continuous, Dma, SampleTime, Scan, Sequence},
+        Adc,
+    },
+    prelude::*,
+    pwm, stm32,
+    stm32::interrupt,
+};
+
+static INJECTED: Mutex<RefCell<Option<Injected>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[interrupt]
+fn ADC() {
+    free(|cs| {
+        if let Some(ref mut injected) = INJECTED.borrow(cs).borrow_mut().deref_mut() {
+            // Only JEOC is enabled. The regular group is serviced by DMA.
+            if let Some(samples) = injected.read() {
+                iprintln!(itm(), "PA0: {} PA1: {}", samples[0], samples[1]);
+            }
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.freeze();
+    let gpioa = dp.GPIOA.split();
+
+    // TIM1 runs at 1 kHz and its update event triggers the injected group
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let mut pwm = pwm::tim1(dp.TIM1, pa8, clocks, 1.khz());
+    let max_duty = pwm.get_max_duty();
+    pwm.set_duty(max_duty / 2);
+    pwm.enable();
+    injected::tim1_trgo_on_update();
+
+    // Regular group: PA3 and PA4 converted continuously
+    let config = AdcConfig::default()
+        .dma(Dma::Continuous)
+        .continuous(Continuous::Continuous)
+        .scan(Scan::Enabled);
+    let mut adc = Adc::adc1(dp.ADC1, true, config);
+    let pa3 = gpioa.pa3.into_analog();
+    let pa4 = gpioa.pa4.into_analog();
+    adc.configure_channel(&pa3, Sequence::One, SampleTime::Cycles_112);
+    adc.configure_channel(&pa4, Sequence::Two, SampleTime::Cycles_112);
+    // Written by DMA2 Stream0 in circular mode
+    let buffer = singleton!(: [u16; 2] = [0; 2]).unwrap();
+    let regular = RegularDma::start(dp.DMA2, buffer);
+
+    // Injected group: PA0 and PA1, sampled on every TIM1 update.
+    // PA1 is offset by half scale to read it as a signed value around mid-rail.
+    let pa0 = gpioa.pa0.into_analog();
+    let pa1 = gpioa.pa1.into_analog();
+    let mut injected = Injected::new(InjectedTrigger::Tim1Trgo);
+    injected.configure_channel(&pa0, 0, SampleTime::Cycles_56);
+    injected.configure_channel(&pa1, 0x0800, SampleTime::Cycles_56);
+    injected.enable_interrupt();
+
+    adc.enable();
+    adc.start_conversion();
+
+    // Move the shared resource to Mutex
+    free(|cs| {
+        INJECTED.borrow(cs).replace(Some(injected));
+    });
+
+    // Enable interrupt
+    unsafe {
+        stm32::NVIC::unmask(stm32::interrupt::ADC);
+    }
+
+    loop {
+        // The regular group is always up to date, no locking required to peek at it
+        let mut samples = [0; 2];
+        regular.read(&mut samples);
+        iprintln!(itm(), "PA3: {} PA4: {}", samples[0], samples[1]);
+        cortex_m::asm::delay(16_000_000);
+    }
+}
+
+mod injected {
+    use stm32f4xx_hal::adc::config::SampleTime;
+    use stm32f4xx_hal::hal::adc::Channel;
+    use stm32f4xx_hal::stm32::{ADC1, DMA2, RCC, TIM1};
+
+    /// Maximum number of conversions in the injected sequence (JDR1..JDR4)
+    pub const MAX_INJECTED: usize = 4;
+
+    /// External trigger sources for the injected group (JEXTSEL)
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, Copy)]
+    pub enum InjectedTrigger {
+        Tim1Cc4 = 0b0000,
+        Tim1Trgo = 0b0001,
+        Tim2Cc1 = 0b0010,
+        Tim2Trgo = 0b0011,
+        Tim3Cc2 = 0b0100,
+        Tim3Cc4 = 0b0101,
+        Tim4Cc1 = 0b0110,
+        Tim4Cc2 = 0b0111,
+        Tim4Cc3 = 0b1000,
+        Tim4Trgo = 0b1001,
+        Tim5Cc4 = 0b1010,
+        Tim5Trgo = 0b1011,
+        Tim8Cc2 = 0b1100,
+        Tim8Cc3 = 0b1101,
+        Tim8Cc4 = 0b1110,
+        Exti15 = 0b1111,
+    }
+
+    /// Injected conversion group of ADC1.
+    /// The regular group is left to `stm32f4xx_hal::adc::Adc`.
+    pub struct Injected {
+        channels: [u8; MAX_INJECTED],
+        len: usize,
+    }
+
+    impl Injected {
+        pub fn new(trigger: InjectedTrigger) -> Self {
+            let adc = unsafe { &(*ADC1::ptr()) };
+            // Rising edge of the selected trigger starts the injected sequence
+            adc.cr2
+                .modify(|_, w| unsafe { w.jexten().bits(0b01).jextsel().bits(trigger as u8) });
+            // Scan mode lets the injected sequence convert all of its channels
+            adc.cr1
+                .modify(|_, w| w.scan().set_bit().jauto().clear_bit());
+            Injected {
+                channels: [0; MAX_INJECTED],
+                len: 0,
+            }
+        }
+
+        /// Appends a channel to the injected sequence.
+        /// `offset` is subtracted from the result by hardware, so the reading can be negative.
+        pub fn configure_channel<PIN>(&mut self, _pin: &PIN, offset: u16, sample_time: SampleTime)
+        where
+            PIN: Channel<ADC1, ID = u8>,
+        {
+            assert!(self.len < MAX_INJECTED, "Injected group is full");
+            let channel = PIN::channel();
+            self.channels[self.len] = channel;
+            self.len += 1;
+
+            let adc = unsafe { &(*ADC1::ptr()) };
+            // Sample time
+            let smp = sample_time as u32;
+            if channel < 10 {
+                let shift = channel as u32 * 3;
+                adc.smpr2
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | smp << shift) });
+            } else {
+                let shift = (channel as u32 - 10) * 3;
+                adc.smpr1
+                    .modify(|r, w| unsafe { w.bits(r.bits() & !(0b111 << shift) | smp << shift) });
+            }
+            // Offset of the n-th conversion goes to JOFRn
+            let offset = (offset & 0x0FFF) as u32;
+            match self.len {
+                1 => adc.jofr1.write(|w| unsafe { w.bits(offset) }),
+                2 => adc.jofr2.write(|w| unsafe { w.bits(offset) }),
+                3 => adc.jofr3.write(|w| unsafe { w.bits(offset) }),
+                _ => adc.jofr4.write(|w| unsafe { w.bits(offset) }),
+            }
+            // A sequence shorter than 4 starts at JSQ(4 - JL), so every rank has to move
+            let jl = self.len as u32 - 1;
+            let first = 3 - jl;
+            let jsqr = self.channels[..self.len]
+                .iter()
+                .enumerate()
+                .fold(jl << 20, |acc, (rank, &ch)| {
+                    acc | (ch as u32) << (5 * (first + rank as u32))
+                });
+            adc.jsqr.write(|w| unsafe { w.bits(jsqr) });
+        }
+
+        /// Enables the JEOC interrupt
+        pub fn enable_interrupt(&mut self) {
+            let adc = unsafe { &(*ADC1::ptr()) };
+            adc.cr1.modify(|_, w| w.jeocie().set_bit());
+        }
+
+        /// Returns the offset-corrected results once JEOC is set, and clears the flag.
+        /// Unused ranks read as 0.
+        pub fn read(&mut self) -> Option<[i16; MAX_INJECTED]> {
+            let adc = unsafe { &(*ADC1::ptr()) };
+            if adc.sr.read().jeoc().bit_is_clear() {
+                return None;
+            }
+            let mut samples = [0; MAX_INJECTED];
+            for (rank, sample) in samples.iter_mut().enumerate().take(self.len) {
+                // JDRx is sign extended when an offset is applied
+                *sample = match rank {
+                    0 => adc.jdr1.read().bits(),
+                    1 => adc.jdr2.read().bits(),
+                    2 => adc.jdr3.read().bits(),
+                    _ => adc.jdr4.read().bits(),
+                } as i16;
+            }
+            // JEOC is cleared by writing 0
+            adc.sr
+                .modify(|_, w| w.jeoc().clear_bit().jstrt().clear_bit());
+            Some(samples)
+        }
+    }
+
+    /// Routes the TIM1 update event to TRGO, so `InjectedTrigger::Tim1Trgo` fires once per period
+    pub fn tim1_trgo_on_update() {
+        let tim1 = unsafe { &(*TIM1::ptr()) };
+        tim1.cr2.modify(|_, w| unsafe { w.mms().bits(0b010) });
+    }
+
+    /// DMA2 Stream0 Channel0 moving ADC1 regular results into a circular buffer
+    pub struct RegularDma {
+        buffer: &'static mut [u16],
+    }
+
+    impl RegularDma {
+        pub fn start(dma: DMA2, buffer: &'static mut [u16]) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+
+            let adc = unsafe { &(*ADC1::ptr()) };
+            let stream = &dma.st[0];
+            stream.cr.modify(|_, w| w.en().clear_bit());
+            while stream.cr.read().en().bit_is_set() {}
+
+            stream
+                .par
+                .write(|w| unsafe { w.bits(&adc.dr as *const _ as u32) });
+            stream
+                .m0ar
+                .write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+            stream
+                .ndtr
+                .write(|w| unsafe { w.bits(buffer.len() as u32) });
+            // Channel 0, half-word to half-word, peripheral to memory, circular
+            stream.cr.write(|w| unsafe {
+                w.chsel()
+                    .bits(0)
+                    .msize()
+                    .bits(0b01)
+                    .psize()
+                    .bits(0b01)
+                    .minc()
+                    .set_bit()
+                    .circ()
+                    .set_bit()
+                    .dir()
+                    .bits(0b00)
+                    .pl()
+                    .bits(0b10)
+            });
+            stream.cr.modify(|_, w| w.en().set_bit());
+            RegularDma { buffer }
+        }
+
+        /// Copies the latest results into `samples`, the DMA keeps writing meanwhile
+        pub fn read(&self, samples: &mut [u16]) {
+            for (sample, latest) in samples.iter_mut().zip(self.buffer.iter()) {
+                *sample = unsafe { core::ptr::read_volatile(latest) };
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use pid::Pid;
+use stm32f4xx_hal::{
+    adc::{config::AdcConfig, config::SampleTime, Adc},
+    gpio::gpioa::PA3,
+    gpio::Analog,
+    prelude::*,
+    pwm, stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+
+#[allow(dead_code)]
+#[path = "shared/pid.rs"]
+mod pid;
+
+/// Control loop rate
+const RATE_HZ: u32 = 1_000;
+/// Setpoints the user button switches between, starting with the low one
+const SETPOINT_LOW: f32 = 0.25;
+const SETPOINT_HIGH: f32 = 0.75;
+
+/// Feedback, actuator and controller used by the control loop
+struct Loop {
+    adc: Adc<stm32::ADC1>,
+    feedback: PA3<Analog>,
+    pwm: pwm::PwmChannels<stm32::TIM1, pwm::C1>,
+    pid: Pid,
+}
+
+static LOOP: Mutex<RefCell<Option<Loop>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(Event::TimeOut);
+        }
+        if let Some(ref mut control) = LOOP.borrow(cs).borrow_mut().deref_mut() {
+            // Normalize feedback and actuator to 0.0..=1.0
+            let sample = control
+                .adc
+                .convert(&control.feedback, SampleTime::Cycles_56);
+            let measurement = sample as f32 / 0x0FFF as f32;
+            let output = control.pid.update(measurement);
+            let max_duty = control.pwm.get_max_duty();
+            control.pwm.set_duty((output * max_duty as f32) as u16);
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+    let gpioc = dp.GPIOC.split();
+
+    // Actuator: 20 kHz PWM on PA8
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let mut pwm = pwm::tim1(dp.TIM1, pa8, clocks, 20.khz());
+    pwm.set_duty(0);
+    pwm.enable();
+
+    // Feedback: PA3
+    let adc = Adc::adc1(dp.ADC1, true, AdcConfig::default());
+    let feedback = gpioa.pa3.into_analog();
+
+    // Controller
+    let mut pid = Pid::new(2.0, 20.0, 0.01, 1.0 / RATE_HZ as f32)
+        .output_limits(0.0, 1.0)
+        .derivative_filter(0.1)
+        .setpoint_ramp(0.5);
+    // Ramps from the first measurement
+    pid.set_setpoint(SETPOINT_LOW);
+
+    // The user button switches between two setpoints
+    let button = gpioc.pc13.into_pull_down_input();
+
+    // Control loop timer
+    let mut timer = Timer::tim2(dp.TIM2, RATE_HZ.hz(), clocks);
+    timer.listen(Event::TimeOut);
+
+    free(|cs| {
+        LOOP.borrow(cs).replace(Some(Loop {
+            adc,
+            feedback,
+            pwm,
+            pid,
+        }));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+    }
+
+    let mut pressed = false;
+    let mut high = false;
+    loop {
+        let is_pressed = button.is_high().unwrap();
+        if is_pressed && !pressed {
+            high = !high;
+            let setpoint = if high { SETPOINT_HIGH } else { SETPOINT_LOW };
+            free(|cs| {
+                if let Some(ref mut control) = LOOP.borrow(cs).borrow_mut().deref_mut() {
+                    control.pid.set_setpoint(setpoint);
+                }
+            });
+        }
+        pressed = is_pressed;
+    }
+}
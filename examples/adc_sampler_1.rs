@@ -0,0 +1,171 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::{
+    interrupt::{free, Mutex},
+    {iprintln, peripheral},
+};
+use cortex_m_rt::entry;
+use sampler::Sampler;
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, Eoc, SampleTime, Sequence},
+        Adc,
+    },
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+};
+use timing::Target;
+
+const BLOCK: usize = 256;
+
+static ADC: Mutex<RefCell<Option<Adc<stm32::ADC1>>>> = Mutex::new(RefCell::new(None));
+static BUFFER: Mutex<RefCell<([u16; BLOCK], usize)>> = Mutex::new(RefCell::new(([0; BLOCK], 0)));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[interrupt]
+fn ADC() {
+    free(|cs| {
+        if let Some(ref mut adc) = ADC.borrow(cs).borrow_mut().deref_mut() {
+            // Reading the result from ADC_DR clears the EOC flag automatically.
+            let sample = adc.current_sample();
+            let mut buffer = BUFFER.borrow(cs).borrow_mut();
+            let (samples, index) = buffer.deref_mut();
+            if *index < BLOCK {
+                samples[*index] = sample;
+                *index += 1;
+            }
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(84.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+
+    // 44.1 kHz sample clock from TIM2 TRGO
+    let mut sampler = Sampler::tim2(dp.TIM2, Target::from_hz(44_100), clocks);
+    let solution = sampler.solution();
+    iprintln!(
+        itm(),
+        "Requested: 44100 Hz, achieved: {} mHz, error {} ppm (PSC {} ARR {})",
+        solution.achieved_millihertz(),
+        solution.error_ppm,
+        solution.psc,
+        solution.arr
+    );
+
+    // Configure ADC
+    let config = AdcConfig::default()
+        .end_of_conversion_interrupt(Eoc::Conversion)
+        .external_trigger(sampler.trigger_mode(), sampler.external_trigger());
+    let mut adc = Adc::adc1(dp.ADC1, true, config);
+    let pa3 = gpioa.pa3.into_analog();
+    adc.configure_channel(&pa3, Sequence::One, SampleTime::Cycles_56);
+    adc.enable();
+
+    // Move the shared resource to Mutex
+    free(|cs| {
+        ADC.borrow(cs).replace(Some(adc));
+    });
+
+    // Enable interrupt
+    unsafe {
+        stm32::NVIC::unmask(stm32::interrupt::ADC);
+    }
+
+    // Start sampling
+    sampler.start();
+
+    loop {
+        // Hand over a full block and start collecting the next one
+        let block = free(|cs| {
+            let mut buffer = BUFFER.borrow(cs).borrow_mut();
+            if buffer.1 == BLOCK {
+                buffer.1 = 0;
+                Some(buffer.0)
+            } else {
+                None
+            }
+        });
+        if let Some(block) = block {
+            let mean = block.iter().map(|&x| x as u32).sum::<u32>() / BLOCK as u32;
+            iprintln!(itm(), "Block mean: {}", mean);
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[path = "shared/timing.rs"]
+mod timing;
+
+mod sampler {
+    use crate::timing::{solve, Solution, Target};
+    use stm32f4xx_hal::adc::config::{ExternalTrigger, TriggerMode};
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{RCC, TIM2};
+
+    /// Fixed-rate ADC sample clock on TIM2. The timer update event is routed to TRGO.
+    pub struct Sampler {
+        timer: TIM2,
+        solution: Solution,
+    }
+
+    impl Sampler {
+        /// Configures the timer to overflow at `rate` as closely as possible
+        pub fn tim2(timer: TIM2, rate: Target, clocks: Clocks) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr.modify(|_, w| w.tim2en().set_bit());
+
+            // APB1 timers run at twice PCLK1 when the APB1 prescaler is not 1
+            let timer_clock = if clocks.ppre1() == 1 {
+                clocks.pclk1().0
+            } else {
+                clocks.pclk1().0 * 2
+            };
+            let solution = solve(timer_clock, rate, 0xFFFF_FFFF);
+
+            timer.cr1.modify(|_, w| w.cen().clear_bit());
+            timer.psc.write(|w| w.psc().bits(solution.psc));
+            timer.arr.write(|w| unsafe { w.bits(solution.arr) });
+            // Update event as TRGO
+            timer.cr2.modify(|_, w| unsafe { w.mms().bits(0b010) });
+            // Load PSC and ARR now
+            timer.egr.write(|w| w.ug().set_bit());
+
+            Sampler { timer, solution }
+        }
+
+        /// ADC external trigger for this timer's TRGO
+        pub fn external_trigger(&self) -> ExternalTrigger {
+            ExternalTrigger::Tim_2_trgo
+        }
+
+        /// Trigger on the rising edge of TRGO
+        pub fn trigger_mode(&self) -> TriggerMode {
+            TriggerMode::RisingEdge
+        }
+
+        /// Starts sampling
+        pub fn start(&mut self) {
+            self.timer.cnt.reset();
+            self.timer.cr1.modify(|_, w| w.cen().set_bit());
+        }
+
+        /// Register values, achieved rate and error
+        pub fn solution(&self) -> &Solution {
+            &self.solution
+        }
+    }
+}
@@ -0,0 +1,106 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use adc_multi::{MultiAdc, MultiMode};
+use cortex_m::{iprintln, peripheral, singleton};
+use cortex_m_rt::entry;
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, Continuous, SampleTime, Sequence},
+        Adc,
+    },
+    prelude::*,
+    stm32,
+};
+
+/// Whole groups for both dual and triple modes
+const LEN: usize = 96;
+
+#[allow(dead_code)]
+#[path = "shared/adc_multi.rs"]
+mod adc_multi;
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let _clocks = rcc.cfgr.freeze();
+    let gpioc = dp.GPIOC.split();
+
+    // PC0-PC3 are wired to all three ADCs (ADC123_IN10-13)
+    let pc0 = gpioc.pc0.into_analog();
+    let pc1 = gpioc.pc1.into_analog();
+    let pc2 = gpioc.pc2.into_analog();
+    let pc3 = gpioc.pc3.into_analog();
+
+    let config = || AdcConfig::default().continuous(Continuous::Continuous);
+    let mut adc1 = Adc::adc1(dp.ADC1, true, config());
+    let mut adc2 = Adc::adc2(dp.ADC2, false, config());
+    let mut adc3 = Adc::adc3(dp.ADC3, false, config());
+
+    let modes = [
+        // PC3 at twice the rate of one ADC
+        MultiMode::DualInterleaved { delay: 5 },
+        // PC0, PC1 and PC2 at the same instant
+        MultiMode::TripleSimultaneous,
+        // PC3 at three times the rate of one ADC
+        MultiMode::TripleInterleaved { delay: 5 },
+    ];
+    // Results in the order ADC1, ADC2(, ADC3)
+    let buffer = singleton!(: [u16; LEN] = [0; LEN]).unwrap();
+    let mut multi = MultiAdc::new(dp.ADC_COMMON, modes[0], buffer);
+
+    loop {
+        for &mode in modes.iter() {
+            adc1.disable();
+            adc2.disable();
+            adc3.disable();
+            multi.stop_dma(&dp.DMA2);
+
+            match mode {
+                MultiMode::DualSimultaneous | MultiMode::TripleSimultaneous => {
+                    adc1.configure_channel(&pc0, Sequence::One, SampleTime::Cycles_84);
+                    adc2.configure_channel(&pc1, Sequence::One, SampleTime::Cycles_84);
+                    adc3.configure_channel(&pc2, Sequence::One, SampleTime::Cycles_84);
+                }
+                // Interleaving only works if sampling fits in the delay
+                MultiMode::DualInterleaved { .. } | MultiMode::TripleInterleaved { .. } => {
+                    adc1.configure_channel(&pc3, Sequence::One, SampleTime::Cycles_3);
+                    adc2.configure_channel(&pc3, Sequence::One, SampleTime::Cycles_3);
+                    adc3.configure_channel(&pc3, Sequence::One, SampleTime::Cycles_3);
+                }
+            }
+            multi.set_mode(mode);
+            multi.start_dma(&dp.DMA2);
+
+            adc3.enable();
+            adc2.enable();
+            adc1.enable();
+            // Starting the master starts the slaves
+            adc1.start_conversion();
+            cortex_m::asm::delay(16_000_000);
+
+            // Mean of each ADC over the buffer
+            let mut samples = [0; LEN];
+            multi.read(&mut samples);
+            let adcs = mode.adcs();
+            let mut sums = [0u32; 3];
+            for group in samples.chunks(adcs) {
+                for (sum, &sample) in sums.iter_mut().zip(group) {
+                    *sum += sample as u32;
+                }
+            }
+            let groups = (LEN / adcs) as u32;
+            iprintln!(itm(), "{:?}", mode);
+            for (i, sum) in sums[..adcs].iter().enumerate() {
+                iprintln!(itm(), "  ADC{}: {}", i + 1, sum / groups);
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use debounce::{Active, Debouncer, Event};
+use stm32f4xx_hal::{
+    gpio::gpiob::{PB14, PB7},
+    gpio::gpioc::{PC10, PC13},
+    gpio::{Input, Output, PullDown, PullUp, PushPull},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{self, Timer},
+};
+
+#[allow(dead_code)]
+#[path = "shared/debounce.rs"]
+mod debounce;
+
+/// Debounce tick, the delays below are counted in these
+const TICK_HZ: u32 = 1_000;
+
+struct Buttons {
+    exti: stm32::EXTI,
+    user: Debouncer<PC13<Input<PullDown>>>,
+    another: Debouncer<PC10<Input<PullUp>>>,
+    led: PB7<Output<PushPull>>,
+    another_led: PB14<Output<PushPull>>,
+}
+
+static BUTTONS: Mutex<RefCell<Option<Buttons>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    // Enable SYSCFG clock for the EXTI line mapping
+    dp.RCC.apb2enr.write(|w| w.syscfgen().enabled());
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // Set up LEDs
+    let gpiob = dp.GPIOB.split();
+    let led = gpiob.pb7.into_push_pull_output();
+    let another_led = gpiob.pb14.into_push_pull_output();
+
+    // The user button reads high when pressed, the other one low
+    let gpioc = dp.GPIOC.split();
+    let user = Debouncer::new(
+        gpioc.pc13.into_pull_down_input(),
+        Active::High,
+        20,
+        &mut dp.SYSCFG,
+        &mut dp.EXTI,
+    );
+    let another = Debouncer::new(
+        gpioc.pc10.into_pull_up_input(),
+        Active::Low,
+        50,
+        &mut dp.SYSCFG,
+        &mut dp.EXTI,
+    );
+
+    // Set up the debounce tick
+    let mut timer = Timer::tim2(dp.TIM2, TICK_HZ.hz(), clocks);
+    timer.listen(timer::Event::TimeOut);
+
+    free(|cs| {
+        BUTTONS.borrow(cs).replace(Some(Buttons {
+            exti: dp.EXTI,
+            user,
+            another,
+            led,
+            another_led,
+        }));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::interrupt::EXTI15_10);
+    stm32::NVIC::unpend(stm32::interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::interrupt::EXTI15_10);
+        stm32::NVIC::unmask(stm32::interrupt::TIM2);
+    }
+
+    loop {}
+}
+
+// First edge of a press or release. Only arms the debouncers.
+#[interrupt]
+fn EXTI15_10() {
+    free(|cs| {
+        if let Some(ref mut b) = BUTTONS.borrow(cs).borrow_mut().deref_mut() {
+            b.user.on_edge(&mut b.exti);
+            b.another.on_edge(&mut b.exti);
+        }
+    });
+}
+
+// Confirms the levels once the bouncing is over and acts on clean events
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(timer::Event::TimeOut);
+        }
+        if let Some(ref mut b) = BUTTONS.borrow(cs).borrow_mut().deref_mut() {
+            if let Some(Event::Pressed) = b.user.on_tick(&mut b.exti) {
+                b.led.toggle().unwrap();
+            }
+            // Lit while held
+            match b.another.on_tick(&mut b.exti) {
+                Some(Event::Pressed) => b.another_led.set_high().unwrap(),
+                Some(Event::Released) => b.another_led.set_low().unwrap(),
+                None => {}
+            }
+        }
+    });
+}
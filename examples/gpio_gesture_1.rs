@@ -0,0 +1,138 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use debounce::{Active, Debouncer};
+use gesture::{Config, Gesture, Recognizer};
+use stm32f4xx_hal::{
+    gpio::gpiob::{PB0, PB14, PB7},
+    gpio::gpioc::PC13,
+    gpio::{Input, Output, PullDown, PushPull},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{self, Timer},
+};
+
+#[allow(dead_code)]
+#[path = "shared/debounce.rs"]
+mod debounce;
+
+#[allow(dead_code)]
+#[path = "shared/gesture.rs"]
+mod gesture;
+
+/// Millisecond tick, the time base of the recognizer
+const TICK_HZ: u32 = 1_000;
+
+struct Button {
+    exti: stm32::EXTI,
+    debouncer: Debouncer<PC13<Input<PullDown>>>,
+    recognizer: Recognizer,
+    now_ms: u32,
+    green: PB0<Output<PushPull>>,
+    blue: PB7<Output<PushPull>>,
+    red: PB14<Output<PushPull>>,
+}
+
+static BUTTON: Mutex<RefCell<Option<Button>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    // Enable SYSCFG clock for the EXTI line mapping
+    dp.RCC.apb2enr.write(|w| w.syscfgen().enabled());
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // Set up LEDs
+    let gpiob = dp.GPIOB.split();
+    let green = gpiob.pb0.into_push_pull_output();
+    let blue = gpiob.pb7.into_push_pull_output();
+    let red = gpiob.pb14.into_push_pull_output();
+
+    // Set up the user button
+    let gpioc = dp.GPIOC.split();
+    let debouncer = Debouncer::new(
+        gpioc.pc13.into_pull_down_input(),
+        Active::High,
+        20,
+        &mut dp.SYSCFG,
+        &mut dp.EXTI,
+    );
+    let config = Config {
+        double_click_ms: 250,
+        long_press_ms: 600,
+        ..Config::default()
+    };
+
+    // Set up the millisecond tick
+    let mut timer = Timer::tim2(dp.TIM2, TICK_HZ.hz(), clocks);
+    timer.listen(timer::Event::TimeOut);
+
+    free(|cs| {
+        BUTTON.borrow(cs).replace(Some(Button {
+            exti: dp.EXTI,
+            debouncer,
+            recognizer: Recognizer::new(config),
+            now_ms: 0,
+            green,
+            blue,
+            red,
+        }));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::interrupt::EXTI15_10);
+    stm32::NVIC::unpend(stm32::interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::interrupt::EXTI15_10);
+        stm32::NVIC::unmask(stm32::interrupt::TIM2);
+    }
+
+    loop {}
+}
+
+#[interrupt]
+fn EXTI15_10() {
+    free(|cs| {
+        if let Some(ref mut b) = BUTTON.borrow(cs).borrow_mut().deref_mut() {
+            b.debouncer.on_edge(&mut b.exti);
+        }
+    });
+}
+
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(timer::Event::TimeOut);
+        }
+        if let Some(ref mut b) = BUTTON.borrow(cs).borrow_mut().deref_mut() {
+            b.now_ms = b.now_ms.wrapping_add(1_000 / TICK_HZ);
+            let now = b.now_ms;
+
+            // Clean edges from the debouncer, then the recognizer timeouts
+            let edge = b
+                .debouncer
+                .on_tick(&mut b.exti)
+                .and_then(|event| b.recognizer.edge(event == debounce::Event::Pressed, now));
+            let gesture = edge.or_else(|| b.recognizer.poll(now));
+
+            match gesture {
+                Some(Gesture::Click) => b.green.toggle().unwrap(),
+                Some(Gesture::DoubleClick) => b.blue.toggle().unwrap(),
+                Some(Gesture::LongPress) => b.red.set_high().unwrap(),
+                Some(Gesture::Repeat) => b.red.toggle().unwrap(),
+                None => {}
+            }
+        }
+    });
+}
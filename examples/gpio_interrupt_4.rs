@@ -0,0 +1,238 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::{Cell, RefCell};
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, CriticalSection, Mutex};
+use cortex_m_rt::entry;
+use stm32f4xx_hal::{
+    gpio::gpiob::{PB0, PB14, PB7},
+    gpio::{Edge, ExtiPin},
+    gpio::{Output, PushPull},
+    prelude::*,
+    stm32,
+};
+
+// Global resources
+static LED: Mutex<RefCell<Option<PB7<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
+static ANOTHER_LED: Mutex<RefCell<Option<PB14<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
+static THIRD_LED: Mutex<RefCell<Option<PB0<Output<PushPull>>>>> = Mutex::new(RefCell::new(None));
+static ANOTHER_BUTTON_ON: Mutex<Cell<bool>> = Mutex::new(Cell::new(true));
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    // Enable GPIO Clock
+    dp.RCC.apb2enr.write(|w| w.syscfgen().enabled());
+
+    // Set up LEDs
+    let gpiob = dp.GPIOB.split();
+    let led = gpiob.pb7.into_push_pull_output();
+    let another_led = gpiob.pb14.into_push_pull_output();
+    let third_led = gpiob.pb0.into_push_pull_output();
+
+    // Set up the user button, EXTI15_10
+    let gpioc = dp.GPIOC.split();
+    let mut user_button = gpioc.pc13.into_pull_down_input();
+    user_button.make_interrupt_source(&mut dp.SYSCFG);
+    user_button.enable_interrupt(&mut dp.EXTI);
+    user_button.trigger_on_edge(&mut dp.EXTI, Edge::RISING);
+
+    // Set up another button, EXTI15_10 too
+    let mut another_button = gpioc.pc10.into_pull_up_input();
+    another_button.make_interrupt_source(&mut dp.SYSCFG);
+    another_button.enable_interrupt(&mut dp.EXTI);
+    another_button.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+
+    // Set up a third button on a line with its own vector, EXTI3
+    let gpioa = dp.GPIOA.split();
+    let mut third_button = gpioa.pa3.into_pull_up_input();
+    third_button.make_interrupt_source(&mut dp.SYSCFG);
+    third_button.enable_interrupt(&mut dp.EXTI);
+    third_button.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+
+    // Move the shared resources to Mutex
+    free(|cs| {
+        LED.borrow(cs).replace(Some(led));
+        ANOTHER_LED.borrow(cs).replace(Some(another_led));
+        THIRD_LED.borrow(cs).replace(Some(third_led));
+    });
+
+    // The dispatcher owns EXTI and clears the pending bits,
+    // the handlers only do their work
+    exti::init(dp.EXTI);
+    exti::register(13, user_button_cb);
+    exti::register(10, another_button_cb);
+    exti::register(3, third_button_cb);
+
+    loop {}
+}
+
+fn user_button_cb(cs: &CriticalSection) {
+    if let Some(ref mut led) = LED.borrow(cs).borrow_mut().deref_mut() {
+        led.toggle().unwrap();
+    }
+}
+
+fn another_button_cb(cs: &CriticalSection) {
+    if let Some(ref mut led) = ANOTHER_LED.borrow(cs).borrow_mut().deref_mut() {
+        led.toggle().unwrap();
+    }
+}
+
+// Switches the other button off and on from inside the dispatch,
+// the third LED is lit while it is off
+fn third_button_cb(cs: &CriticalSection) {
+    if let Some(ref mut led) = THIRD_LED.borrow(cs).borrow_mut().deref_mut() {
+        led.toggle().unwrap();
+    }
+    let on = ANOTHER_BUTTON_ON.borrow(cs);
+    if on.get() {
+        exti::unregister_in(cs, 10);
+    } else {
+        exti::register_in(cs, 10, another_button_cb);
+    }
+    on.set(!on.get());
+}
+
+/// EXTI line dispatcher for lines 0 to 15.
+///
+/// Handlers run inside the critical section of the interrupt, so they borrow
+/// their resources with the `CriticalSection` they get instead of nesting `free()`.
+mod exti {
+    use core::cell::RefCell;
+    use cortex_m::interrupt::{free, CriticalSection, Mutex};
+    use stm32f4xx_hal::stm32::{self, interrupt, Interrupt, EXTI};
+
+    pub type Handler = fn(&CriticalSection);
+
+    struct Dispatcher {
+        exti: EXTI,
+        handlers: [Option<Handler>; 16],
+    }
+
+    static DISPATCHER: Mutex<RefCell<Option<Dispatcher>>> = Mutex::new(RefCell::new(None));
+
+    /// Takes over EXTI. The pin side (source, edges, mask) is set up with `ExtiPin` as before.
+    pub fn init(exti: EXTI) {
+        free(|cs| {
+            DISPATCHER.borrow(cs).replace(Some(Dispatcher {
+                exti,
+                handlers: [None; 16],
+            }));
+        });
+    }
+
+    /// Calls `handler` on every event of `line` and unmasks the line's vector in the NVIC.
+    /// Panics before `init`.
+    pub fn register(line: u8, handler: Handler) {
+        free(|cs| register_in(cs, line, handler));
+    }
+
+    /// `register` for use inside a handler, with the handler's `CriticalSection`
+    /// instead of a nested `free()`. Lines already pending in the running dispatch
+    /// still get the handlers they had, the change applies from the next one.
+    pub fn register_in(cs: &CriticalSection, line: u8, handler: Handler) {
+        assert!(line < 16);
+        // Without the dispatcher the vector would never clear the line and fire forever
+        match *DISPATCHER.borrow(cs).borrow_mut() {
+            Some(ref mut dispatcher) => dispatcher.handlers[line as usize] = Some(handler),
+            None => panic!("exti::init first"),
+        }
+        let irq = vector(line);
+        stm32::NVIC::unpend(irq);
+        unsafe {
+            stm32::NVIC::unmask(irq);
+        }
+    }
+
+    /// Removes the handler of `line`. Its events are still cleared.
+    #[allow(dead_code)]
+    pub fn unregister(line: u8) {
+        free(|cs| unregister_in(cs, line));
+    }
+
+    /// `unregister` for use inside a handler, see `register_in`
+    pub fn unregister_in(cs: &CriticalSection, line: u8) {
+        assert!(line < 16);
+        if let Some(ref mut dispatcher) = *DISPATCHER.borrow(cs).borrow_mut() {
+            dispatcher.handlers[line as usize] = None;
+        }
+    }
+
+    /// Vector shared by `line`
+    pub fn vector(line: u8) -> Interrupt {
+        match line {
+            0 => Interrupt::EXTI0,
+            1 => Interrupt::EXTI1,
+            2 => Interrupt::EXTI2,
+            3 => Interrupt::EXTI3,
+            4 => Interrupt::EXTI4,
+            5..=9 => Interrupt::EXTI9_5,
+            _ => Interrupt::EXTI15_10,
+        }
+    }
+
+    /// Handles the pending lines from `first` to `last`
+    fn dispatch(first: u8, last: u8) {
+        free(|cs| {
+            let (pending, handlers) = match *DISPATCHER.borrow(cs).borrow() {
+                Some(ref dispatcher) => {
+                    let mask = (0xFFFF >> (15 - last)) & !((1 << first) - 1);
+                    let pending =
+                        dispatcher.exti.pr.read().bits() & dispatcher.exti.imr.read().bits() & mask;
+                    // Clear before the handlers run, so an edge during them is not lost
+                    dispatcher.exti.pr.write(|w| unsafe { w.bits(pending) });
+                    (pending, dispatcher.handlers)
+                }
+                None => return,
+            };
+            // The table is copied out and the borrow released, so handlers
+            // can `register_in`/`unregister_in`
+            for line in first..=last {
+                if pending & (1 << line) != 0 {
+                    if let Some(handler) = handlers[line as usize] {
+                        handler(cs);
+                    }
+                }
+            }
+        });
+    }
+
+    #[interrupt]
+    fn EXTI0() {
+        dispatch(0, 0);
+    }
+
+    #[interrupt]
+    fn EXTI1() {
+        dispatch(1, 1);
+    }
+
+    #[interrupt]
+    fn EXTI2() {
+        dispatch(2, 2);
+    }
+
+    #[interrupt]
+    fn EXTI3() {
+        dispatch(3, 3);
+    }
+
+    #[interrupt]
+    fn EXTI4() {
+        dispatch(4, 4);
+    }
+
+    #[interrupt]
+    fn EXTI9_5() {
+        dispatch(5, 9);
+    }
+
+    #[interrupt]
+    fn EXTI15_10() {
+        dispatch(10, 15);
+    }
+}
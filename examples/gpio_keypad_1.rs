@@ -0,0 +1,388 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::{iprintln, peripheral};
+use cortex_m_rt::entry;
+use keypad::{Event, Keymap, Keypad};
+use stm32f4xx_hal::{
+    gpio::gpioe::{PE10, PE11, PE12, PE13, PE14, PE15, PE8, PE9},
+    gpio::{Edge, ExtiPin, Input, OpenDrain, Output, PullUp},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{self, Timer},
+};
+
+/// Scan tick, one row per tick
+const TICK_HZ: u32 = 1_000;
+/// Ticks without any key down before the scan stops and EXTI takes over
+const IDLE_TICKS: u32 = 1_000;
+/// EXTI lines of the columns
+const COLUMN_LINES: u32 = 0xF000;
+
+/// Layouts in turn, the bottom left and right keys together switch to the next
+const LAYOUTS: [(&str, Keymap); 2] = [
+    (
+        "phone",
+        [
+            ['1', '2', '3', 'A'],
+            ['4', '5', '6', 'B'],
+            ['7', '8', '9', 'C'],
+            ['*', '0', '#', 'D'],
+        ],
+    ),
+    (
+        "hex",
+        [
+            ['1', '2', '3', 'A'],
+            ['4', '5', '6', 'B'],
+            ['7', '8', '9', 'C'],
+            ['E', '0', 'F', 'D'],
+        ],
+    ),
+];
+
+type Rows = (
+    PE8<Output<OpenDrain>>,
+    PE9<Output<OpenDrain>>,
+    PE10<Output<OpenDrain>>,
+    PE11<Output<OpenDrain>>,
+);
+type Columns = (
+    PE12<Input<PullUp>>,
+    PE13<Input<PullUp>>,
+    PE14<Input<PullUp>>,
+    PE15<Input<PullUp>>,
+);
+
+struct Panel {
+    keypad: Keypad<Rows, Columns>,
+    timer: Timer<stm32::TIM2>,
+    exti: stm32::EXTI,
+    idle: u32,
+    /// Index into `LAYOUTS`
+    layout: usize,
+}
+
+impl Panel {
+    /// Scans again, ends the sleep started in `TIM2`
+    fn wake(&mut self) {
+        self.exti
+            .imr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !COLUMN_LINES) });
+        self.exti.pr.write(|w| unsafe { w.bits(COLUMN_LINES) });
+        self.keypad.resume();
+        self.idle = 0;
+        self.timer.listen(timer::Event::TimeOut);
+    }
+}
+
+static PANEL: Mutex<RefCell<Option<Panel>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    // Enable SYSCFG clock for the EXTI line mapping
+    dp.RCC.apb2enr.write(|w| w.syscfgen().enabled());
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // Rows PE8-PE11 open drain, columns PE12-PE15 with pull-ups.
+    // Open drain rows keep two keys in one column from shorting the rows.
+    let gpioe = dp.GPIOE.split();
+    let rows = (
+        gpioe.pe8.into_open_drain_output(),
+        gpioe.pe9.into_open_drain_output(),
+        gpioe.pe10.into_open_drain_output(),
+        gpioe.pe11.into_open_drain_output(),
+    );
+    let mut columns = (
+        gpioe.pe12.into_pull_up_input(),
+        gpioe.pe13.into_pull_up_input(),
+        gpioe.pe14.into_pull_up_input(),
+        gpioe.pe15.into_pull_up_input(),
+    );
+    // Falling edge on any column wakes the scanner. Masked until parked.
+    columns.0.make_interrupt_source(&mut dp.SYSCFG);
+    columns.0.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+    columns.1.make_interrupt_source(&mut dp.SYSCFG);
+    columns.1.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+    columns.2.make_interrupt_source(&mut dp.SYSCFG);
+    columns.2.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+    columns.3.make_interrupt_source(&mut dp.SYSCFG);
+    columns.3.trigger_on_edge(&mut dp.EXTI, Edge::FALLING);
+
+    let keypad = Keypad::new(rows, columns, LAYOUTS[0].1, 5);
+
+    // Set up the scan tick
+    let mut timer = Timer::tim2(dp.TIM2, TICK_HZ.hz(), clocks);
+    timer.listen(timer::Event::TimeOut);
+
+    free(|cs| {
+        PANEL.borrow(cs).replace(Some(Panel {
+            keypad,
+            timer,
+            exti: dp.EXTI,
+            idle: 0,
+            layout: 0,
+        }));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    stm32::NVIC::unpend(stm32::Interrupt::EXTI15_10);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+        stm32::NVIC::unmask(stm32::Interrupt::EXTI15_10);
+    }
+
+    loop {
+        // Sleeps between ticks and while parked
+        cortex_m::asm::wfi();
+    }
+}
+
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut panel) = PANEL.borrow(cs).borrow_mut().deref_mut() {
+            panel.timer.clear_interrupt(timer::Event::TimeOut);
+            let mut pressed = false;
+            panel.keypad.tick(|event| match event {
+                Event::Pressed(key) => {
+                    iprintln!(itm(), "{} down", key);
+                    pressed = true;
+                }
+                Event::Released(key) => iprintln!(itm(), "{} up", key),
+            });
+
+            let keymap = LAYOUTS[panel.layout].1;
+            if pressed
+                && panel.keypad.is_pressed(keymap[3][0])
+                && panel.keypad.is_pressed(keymap[3][2])
+            {
+                panel.layout = (panel.layout + 1) % LAYOUTS.len();
+                let (name, keymap) = LAYOUTS[panel.layout];
+                panel.keypad.set_keymap(keymap);
+                iprintln!(itm(), "{} layout", name);
+            }
+
+            panel.idle = if panel.keypad.any_pressed() {
+                0
+            } else {
+                panel.idle + 1
+            };
+            if panel.idle >= IDLE_TICKS {
+                // Unmask first, PR only latches edges on unmasked lines
+                panel.exti.pr.write(|w| unsafe { w.bits(COLUMN_LINES) });
+                panel
+                    .exti
+                    .imr
+                    .modify(|r, w| unsafe { w.bits(r.bits() | COLUMN_LINES) });
+                // Stop scanning, any key pulls its column low now
+                panel.timer.unlisten(timer::Event::TimeOut);
+                panel.keypad.park();
+                // A key that was already down on the last scanned row makes no edge
+                if panel.keypad.any_column_low() {
+                    panel.wake();
+                }
+            }
+        }
+    });
+}
+
+// Wake up on a key press while parked
+#[interrupt]
+fn EXTI15_10() {
+    free(|cs| {
+        if let Some(ref mut panel) = PANEL.borrow(cs).borrow_mut().deref_mut() {
+            panel.wake();
+        }
+    });
+}
+
+mod keypad {
+    use stm32f4xx_hal::hal::digital::v2::{InputPin, OutputPin};
+
+    pub const ROWS: usize = 4;
+    pub const COLUMNS: usize = 4;
+
+    /// Key labels by row and column
+    pub type Keymap = [[char; COLUMNS]; ROWS];
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Event {
+        Pressed(char),
+        Released(char),
+    }
+
+    /// Row outputs, active low
+    pub trait Rows {
+        /// Drives `row` low and the others high (released)
+        fn select(&mut self, row: usize);
+        /// Drives every row low
+        fn select_all(&mut self);
+    }
+
+    /// Column inputs with pull-ups, one bit per column, set while low
+    pub trait Columns {
+        fn read(&self) -> u8;
+    }
+
+    impl<R0, R1, R2, R3> Rows for (R0, R1, R2, R3)
+    where
+        R0: OutputPin,
+        R1: OutputPin,
+        R2: OutputPin,
+        R3: OutputPin,
+    {
+        fn select(&mut self, row: usize) {
+            // Release first, so two rows are never driven at once
+            self.0.set_high().ok();
+            self.1.set_high().ok();
+            self.2.set_high().ok();
+            self.3.set_high().ok();
+            match row {
+                0 => self.0.set_low().ok(),
+                1 => self.1.set_low().ok(),
+                2 => self.2.set_low().ok(),
+                _ => self.3.set_low().ok(),
+            };
+        }
+
+        fn select_all(&mut self) {
+            self.0.set_low().ok();
+            self.1.set_low().ok();
+            self.2.set_low().ok();
+            self.3.set_low().ok();
+        }
+    }
+
+    impl<C0, C1, C2, C3> Columns for (C0, C1, C2, C3)
+    where
+        C0: InputPin,
+        C1: InputPin,
+        C2: InputPin,
+        C3: InputPin,
+    {
+        fn read(&self) -> u8 {
+            low(&self.0) | low(&self.1) << 1 | low(&self.2) << 2 | low(&self.3) << 3
+        }
+    }
+
+    fn low<P: InputPin>(pin: &P) -> u8 {
+        pin.is_low().unwrap_or(false) as u8
+    }
+
+    /// Scans one row per tick and debounces every key on its own.
+    ///
+    /// With a diode per key any combination is read correctly (n-key rollover).
+    /// Without diodes three keys on the corners of a rectangle show a fourth.
+    pub struct Keypad<R, C> {
+        rows: R,
+        columns: C,
+        keymap: Keymap,
+        /// Row being driven, read on the next tick
+        row: usize,
+        /// Scans a key has to be stable for
+        debounce: u8,
+        /// Per-key integrator, 0 to `debounce`
+        counters: [[u8; COLUMNS]; ROWS],
+        /// Debounced state, bit `row * COLUMNS + column`
+        pressed: u16,
+    }
+
+    impl<R, C> Keypad<R, C>
+    where
+        R: Rows,
+        C: Columns,
+    {
+        /// A key changes state after `debounce` consistent scans
+        pub fn new(mut rows: R, columns: C, keymap: Keymap, debounce: u8) -> Self {
+            rows.select(0);
+            Keypad {
+                rows,
+                columns,
+                keymap,
+                row: 0,
+                debounce: debounce.max(1),
+                counters: [[0; COLUMNS]; ROWS],
+                pressed: 0,
+            }
+        }
+
+        /// Switches to another layout. Keys held now are released with their new labels.
+        pub fn set_keymap(&mut self, keymap: Keymap) {
+            self.keymap = keymap;
+        }
+
+        /// Reads the selected row and selects the next one.
+        /// The row has had a full tick to settle.
+        pub fn tick<F>(&mut self, mut f: F)
+        where
+            F: FnMut(Event),
+        {
+            let raw = self.columns.read();
+            let row = self.row;
+            for column in 0..COLUMNS {
+                let counter = &mut self.counters[row][column];
+                if raw & (1 << column) != 0 {
+                    *counter = counter.saturating_add(1).min(self.debounce);
+                } else {
+                    *counter = counter.saturating_sub(1);
+                }
+
+                let bit = 1 << (row * COLUMNS + column);
+                let was = self.pressed & bit != 0;
+                let key = self.keymap[row][column];
+                if !was && *counter == self.debounce {
+                    self.pressed |= bit;
+                    f(Event::Pressed(key));
+                } else if was && *counter == 0 {
+                    self.pressed &= !bit;
+                    f(Event::Released(key));
+                }
+            }
+            self.row = (row + 1) % ROWS;
+            self.rows.select(self.row);
+        }
+
+        /// True while `key` is down
+        pub fn is_pressed(&self, key: char) -> bool {
+            (0..ROWS * COLUMNS).any(|i| {
+                self.pressed & (1 << i) != 0 && self.keymap[i / COLUMNS][i % COLUMNS] == key
+            })
+        }
+
+        pub fn any_pressed(&self) -> bool {
+            self.pressed != 0
+        }
+
+        /// Drives all rows so a press on any key shows on the columns.
+        /// Stop calling `tick` until `resume`.
+        pub fn park(&mut self) {
+            self.rows.select_all();
+        }
+
+        /// True if a key pulls a column low, raw and undebounced.
+        /// After `park` that is any key.
+        pub fn any_column_low(&self) -> bool {
+            self.columns.read() != 0
+        }
+
+        /// Restarts scanning from the first row
+        pub fn resume(&mut self) {
+            self.row = 0;
+            self.rows.select(0);
+        }
+    }
+}
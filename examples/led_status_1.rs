@@ -0,0 +1,268 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use status::{Indicator, Pattern};
+use stm32f4xx_hal::{
+    gpio::gpiob::{PB0, PB14, PB7},
+    gpio::{Output, PushPull},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+
+/// Indicator tick
+const TICK_MS: u32 = 10;
+/// Custom pattern, a long flash followed by two short ones
+const LONG_SHORT_SHORT: [u16; 6] = [400, 150, 100, 150, 100, 800];
+
+struct Indicators {
+    green: Indicator<PB0<Output<PushPull>>>,
+    blue: Indicator<PB7<Output<PushPull>>>,
+    red: Indicator<PB14<Output<PushPull>>>,
+}
+
+static INDICATORS: Mutex<RefCell<Option<Indicators>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(Event::TimeOut);
+        }
+        if let Some(ref mut leds) = INDICATORS.borrow(cs).borrow_mut().deref_mut() {
+            leds.green.tick(TICK_MS);
+            leds.blue.tick(TICK_MS);
+            leds.red.tick(TICK_MS);
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // LEDs show the state, the user button steps the blue one through the patterns
+    let gpiob = dp.GPIOB.split();
+    let leds = Indicators {
+        green: Indicator::new(gpiob.pb0.into_push_pull_output(), Pattern::Heartbeat),
+        blue: Indicator::new(gpiob.pb7.into_push_pull_output(), Pattern::Off),
+        red: Indicator::new(gpiob.pb14.into_push_pull_output(), Pattern::Code(3)),
+    };
+    let gpioc = dp.GPIOC.split();
+    let button = gpioc.pc13.into_pull_down_input();
+
+    // Set up the tick
+    let mut timer = Timer::tim2(dp.TIM2, (1_000 / TICK_MS).hz(), clocks);
+    timer.listen(Event::TimeOut);
+
+    free(|cs| {
+        INDICATORS.borrow(cs).replace(Some(leds));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+    }
+
+    let patterns = [
+        Pattern::Off,
+        Pattern::On,
+        Pattern::SLOW,
+        Pattern::FAST,
+        Pattern::Heartbeat,
+        Pattern::Sos,
+        Pattern::Code(5),
+        Pattern::Sequence(&LONG_SHORT_SHORT),
+    ];
+    let mut index = 0;
+    let mut was_pressed = false;
+    loop {
+        let pressed = button.is_high().unwrap();
+        if pressed && !was_pressed {
+            index = (index + 1) % patterns.len();
+            free(|cs| {
+                if let Some(ref mut leds) = INDICATORS.borrow(cs).borrow_mut().deref_mut() {
+                    leds.blue.set(patterns[index]);
+                }
+            });
+        }
+        was_pressed = pressed;
+        // Crude debounce
+        cortex_m::asm::delay(48_000_000 / 50);
+    }
+}
+
+mod status {
+    use stm32f4xx_hal::hal::digital::v2::OutputPin;
+
+    /// Morse timing unit of the SOS pattern
+    const DOT_MS: u16 = 150;
+    /// On/off durations of one error code blink
+    const CODE_ON_MS: u16 = 200;
+    const CODE_OFF_MS: u16 = 300;
+    /// Gap before an error code repeats
+    const CODE_PAUSE_MS: u16 = 1_500;
+
+    /// Two short beats and a rest
+    const HEARTBEAT: [u16; 4] = [80, 120, 80, 720];
+    /// ... --- ... with a word gap
+    const SOS: [u16; 18] = [
+        DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        3 * DOT_MS,
+        3 * DOT_MS,
+        DOT_MS,
+        3 * DOT_MS,
+        DOT_MS,
+        3 * DOT_MS,
+        3 * DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        DOT_MS,
+        7 * DOT_MS,
+    ];
+
+    /// Repeating blink patterns
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Pattern {
+        Off,
+        On,
+        Blink {
+            on_ms: u16,
+            off_ms: u16,
+        },
+        Heartbeat,
+        Sos,
+        /// N blinks and a pause, N from 1
+        Code(u8),
+        /// Durations in ms, alternating on and off, starting with on
+        Sequence(&'static [u16]),
+    }
+
+    impl Pattern {
+        pub const SLOW: Pattern = Pattern::Blink {
+            on_ms: 500,
+            off_ms: 500,
+        };
+        pub const FAST: Pattern = Pattern::Blink {
+            on_ms: 100,
+            off_ms: 100,
+        };
+
+        /// Level and length of step `index`, `None` past the end
+        fn step(self, index: usize) -> Option<(bool, u16)> {
+            let on = index % 2 == 0;
+            let from = |table: &[u16]| table.get(index).map(|&ms| (on, ms));
+            match self {
+                // Steady patterns are one long step
+                Pattern::Off => Some((false, u16::MAX)).filter(|_| index == 0),
+                Pattern::On => Some((true, u16::MAX)).filter(|_| index == 0),
+                Pattern::Blink { on_ms, off_ms } => match index {
+                    0 => Some((true, on_ms)),
+                    1 => Some((false, off_ms)),
+                    _ => None,
+                },
+                Pattern::Heartbeat => from(&HEARTBEAT),
+                Pattern::Sos => from(&SOS),
+                Pattern::Code(n) => {
+                    let steps = 2 * n.max(1) as usize;
+                    if index + 1 < steps {
+                        Some((on, if on { CODE_ON_MS } else { CODE_OFF_MS }))
+                    } else if index + 1 == steps {
+                        Some((false, CODE_PAUSE_MS))
+                    } else {
+                        None
+                    }
+                }
+                Pattern::Sequence(table) => from(table),
+            }
+        }
+    }
+
+    /// Plays a pattern on an output pin, stepped by a timer tick
+    pub struct Indicator<P> {
+        pin: P,
+        pattern: Pattern,
+        step: usize,
+        /// Time left in the current step
+        remaining_ms: u32,
+    }
+
+    impl<P> Indicator<P>
+    where
+        P: OutputPin,
+    {
+        pub fn new(pin: P, pattern: Pattern) -> Self {
+            let mut indicator = Indicator {
+                pin,
+                pattern,
+                step: 0,
+                remaining_ms: 0,
+            };
+            indicator.restart();
+            indicator
+        }
+
+        /// Switches to `pattern` from its start. The same pattern keeps running undisturbed.
+        pub fn set(&mut self, pattern: Pattern) {
+            if pattern != self.pattern {
+                self.pattern = pattern;
+                self.restart();
+            }
+        }
+
+        /// Advances by `ms`, call from a periodic tick
+        pub fn tick(&mut self, ms: u32) {
+            if let Pattern::Off | Pattern::On = self.pattern {
+                return;
+            }
+            let mut elapsed = ms;
+            while elapsed >= self.remaining_ms {
+                elapsed -= self.remaining_ms;
+                self.step += 1;
+                self.enter();
+            }
+            self.remaining_ms -= elapsed;
+        }
+
+        fn restart(&mut self) {
+            self.step = 0;
+            self.enter();
+        }
+
+        /// Applies the current step, wrapping around at the end
+        fn enter(&mut self) {
+            let (on, ms) = match self.pattern.step(self.step) {
+                Some(step) => step,
+                None => {
+                    self.step = 0;
+                    self.pattern.step(0).unwrap_or((false, u16::MAX))
+                }
+            };
+            // Zero length steps would stall `tick`
+            self.remaining_ms = ms.max(1) as u32;
+            if on {
+                self.pin.set_high().ok();
+            } else {
+                self.pin.set_low().ok();
+            }
+        }
+    }
+}
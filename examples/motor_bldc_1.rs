@@ -0,0 +1,373 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use bridge::Bridge;
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::{
+    interrupt::{free, Mutex},
+    {iprintln, peripheral},
+};
+use cortex_m_rt::entry;
+use motor::{Direction, Phase};
+use stm32f4xx_hal::{
+    gpio::{Edge, ExtiPin},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+};
+
+/// Six-step from the hall sensors, or open-loop sinusoidal drive with SVPWM
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    SixStep,
+    Svpwm,
+}
+
+const MODE: Mode = Mode::SixStep;
+
+/// Drive state shared by the interrupt handlers
+struct Drive {
+    bridge: Bridge,
+    direction: Direction,
+    /// Six-step duty, 0..=max_duty
+    duty: u16,
+    /// SVPWM electrical angle, a full turn is 65536
+    angle: u16,
+    /// SVPWM angle step per PWM period
+    speed: u16,
+    /// SVPWM amplitude, Q15
+    amplitude: i16,
+    /// Latest phase currents from the injected group
+    currents: [u16; 2],
+}
+
+#[allow(dead_code)]
+#[path = "shared/dead_time.rs"]
+mod dead_time;
+#[path = "shared/motor.rs"]
+mod motor;
+
+static DRIVE: Mutex<RefCell<Option<Drive>>> = Mutex::new(RefCell::new(None));
+static EXTI: Mutex<RefCell<Option<stm32::EXTI>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+/// Reads the hall sensors on PC6, PC7 and PC8 as H3 H2 H1
+fn hall_state() -> u8 {
+    let gpioc = unsafe { &(*stm32::GPIOC::ptr()) };
+    ((gpioc.idr.read().bits() >> 6) & 0b111) as u8
+}
+
+fn commutate(drive: &mut Drive) {
+    match motor::commutation(hall_state(), drive.direction) {
+        Some(phases) => drive.bridge.six_step(phases, drive.duty),
+        // 0b000 and 0b111 are not valid hall states. Coast.
+        None => drive.bridge.six_step([Phase::Float; 3], 0),
+    }
+}
+
+// Hall sensor edges
+#[interrupt]
+fn EXTI9_5() {
+    free(|cs| {
+        if let Some(ref mut exti) = EXTI.borrow(cs).borrow_mut().deref_mut() {
+            // Clear the interrupt flags of lines 6, 7 and 8
+            exti.pr
+                .write(|w| w.pr6().set_bit().pr7().set_bit().pr8().set_bit());
+        }
+        if let Some(ref mut drive) = DRIVE.borrow(cs).borrow_mut().deref_mut() {
+            if MODE == Mode::SixStep {
+                commutate(drive);
+            }
+        }
+    });
+}
+
+// PWM period, advances the SVPWM angle
+#[interrupt]
+fn TIM1_UP_TIM10() {
+    free(|cs| {
+        if let Some(ref mut drive) = DRIVE.borrow(cs).borrow_mut().deref_mut() {
+            drive.bridge.clear_update();
+            if MODE == Mode::Svpwm {
+                drive.angle = match drive.direction {
+                    Direction::Forward => drive.angle.wrapping_add(drive.speed),
+                    Direction::Reverse => drive.angle.wrapping_sub(drive.speed),
+                };
+                let duties = motor::svpwm(drive.angle, drive.amplitude);
+                drive.bridge.set_duties(duties);
+            }
+        }
+    });
+}
+
+// Injected conversions triggered by TIM1 CC4 in the middle of the low-side on time
+#[interrupt]
+fn ADC() {
+    free(|cs| {
+        if let Some(ref mut drive) = DRIVE.borrow(cs).borrow_mut().deref_mut() {
+            drive.currents = bridge::read_currents();
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(168.mhz()).freeze();
+    dp.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+
+    // High side on PA8, PA9, PA10 and low side on PB13, PB14, PB15
+    let _high = (
+        gpioa.pa8.into_alternate_af1(),
+        gpioa.pa9.into_alternate_af1(),
+        gpioa.pa10.into_alternate_af1(),
+    );
+    let _low = (
+        gpiob.pb13.into_alternate_af1(),
+        gpiob.pb14.into_alternate_af1(),
+        gpiob.pb15.into_alternate_af1(),
+    );
+    // Phase A and B current shunts on PA0 and PA1
+    let _shunts = (gpioa.pa0.into_analog(), gpioa.pa1.into_analog());
+
+    // 20 kHz center-aligned PWM with 1 µs dead time
+    let bridge = Bridge::tim1(dp.TIM1, clocks, 20_000, 1_000);
+    bridge::injected_currents(dp.ADC1);
+
+    // Hall sensors on PC6, PC7 and PC8, both edges
+    let mut h1 = gpioc.pc6.into_pull_up_input();
+    let mut h2 = gpioc.pc7.into_pull_up_input();
+    let mut h3 = gpioc.pc8.into_pull_up_input();
+    h1.make_interrupt_source(&mut dp.SYSCFG);
+    h1.enable_interrupt(&mut dp.EXTI);
+    h1.trigger_on_edge(&mut dp.EXTI, Edge::RISING_FALLING);
+    h2.make_interrupt_source(&mut dp.SYSCFG);
+    h2.enable_interrupt(&mut dp.EXTI);
+    h2.trigger_on_edge(&mut dp.EXTI, Edge::RISING_FALLING);
+    h3.make_interrupt_source(&mut dp.SYSCFG);
+    h3.enable_interrupt(&mut dp.EXTI);
+    h3.trigger_on_edge(&mut dp.EXTI, Edge::RISING_FALLING);
+
+    let mut drive = Drive {
+        duty: bridge.max_duty() / 4,
+        bridge,
+        direction: Direction::Forward,
+        angle: 0,
+        // About 2 Hz electrical at 20 k updates per second
+        speed: 7,
+        amplitude: 8_000,
+        currents: [0; 2],
+    };
+    // Apply the first step before any hall edge arrives
+    match MODE {
+        Mode::SixStep => commutate(&mut drive),
+        Mode::Svpwm => drive.bridge.sinusoidal(),
+    }
+    drive.bridge.enable_outputs();
+
+    free(|cs| {
+        DRIVE.borrow(cs).replace(Some(drive));
+        EXTI.borrow(cs).replace(Some(dp.EXTI));
+    });
+
+    // Enable interrupts
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::EXTI9_5);
+        stm32::NVIC::unmask(stm32::Interrupt::TIM1_UP_TIM10);
+        stm32::NVIC::unmask(stm32::Interrupt::ADC);
+    }
+
+    loop {
+        let currents = free(|cs| {
+            DRIVE
+                .borrow(cs)
+                .borrow()
+                .as_ref()
+                .map(|drive| drive.currents)
+        });
+        if let Some([ia, ib]) = currents {
+            iprintln!(itm(), "hall: {:03b} Ia: {} Ib: {}", hall_state(), ia, ib);
+        }
+        cortex_m::asm::delay(168_000_000);
+    }
+}
+
+/// TIM1 three-phase bridge and ADC1 injected current sampling
+mod bridge {
+    use crate::dead_time::dead_time_bits;
+    use crate::motor::Phase;
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{ADC1, ADC_COMMON, RCC, TIM1};
+
+    pub struct Bridge {
+        tim: TIM1,
+        max_duty: u16,
+    }
+
+    impl Bridge {
+        /// Center-aligned PWM on CH1-CH3 with complementary outputs, CH4 triggers the ADC
+        pub fn tim1(tim: TIM1, clocks: Clocks, frequency: u32, dead_time_ns: u32) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb2enr.modify(|_, w| w.tim1en().set_bit());
+
+            let clock = if clocks.ppre2() == 1 {
+                clocks.pclk2().0
+            } else {
+                clocks.pclk2().0 * 2
+            };
+            // Up/down counting, one period is 2 * ARR ticks
+            let arr = clock / frequency / 2;
+            tim.psc.write(|w| w.psc().bits(0));
+            tim.arr.write(|w| unsafe { w.bits(arr) });
+            tim.cr1
+                .write(|w| unsafe { w.cms().bits(0b01).arpe().set_bit() });
+            // Preload CCxE/CCxNE/OCxM, commutations take effect together on COM
+            tim.cr2.modify(|_, w| w.ccpc().set_bit());
+
+            tim.ccmr1_output().write(|w| {
+                w.oc1pe()
+                    .set_bit()
+                    .oc1m()
+                    .pwm_mode1()
+                    .oc2pe()
+                    .set_bit()
+                    .oc2m()
+                    .pwm_mode1()
+            });
+            tim.ccmr2_output().write(|w| {
+                w.oc3pe()
+                    .set_bit()
+                    .oc3m()
+                    .pwm_mode1()
+                    .oc4pe()
+                    .set_bit()
+                    .oc4m()
+                    .pwm_mode2()
+            });
+            // CC4 fires at the top of the count, when all low sides are on
+            tim.ccr4.write(|w| unsafe { w.bits(arr - 1) });
+            tim.ccer.modify(|_, w| w.cc4e().set_bit());
+
+            // Dead time in t_DTS = 1 / timer clock (CKD = 0)
+            let dtg = dead_time_bits(dead_time_ns, clock).expect("Dead-time too long");
+            tim.bdtr
+                .write(|w| unsafe { w.dtg().bits(dtg).ossr().set_bit().ossi().set_bit() });
+
+            // Center-aligned counting updates at both ends, one update per period is enough
+            tim.rcr.write(|w| unsafe { w.rep().bits(1) });
+            tim.dier.modify(|_, w| w.uie().set_bit());
+            tim.egr.write(|w| w.ug().set_bit());
+            tim.cr1.modify(|_, w| w.cen().set_bit());
+
+            Bridge {
+                tim,
+                max_duty: arr as u16,
+            }
+        }
+
+        pub fn max_duty(&self) -> u16 {
+            self.max_duty
+        }
+
+        pub fn enable_outputs(&mut self) {
+            self.tim.bdtr.modify(|_, w| w.moe().set_bit());
+        }
+
+        pub fn clear_update(&mut self) {
+            self.tim.sr.modify(|_, w| w.uif().clear_bit());
+        }
+
+        /// Applies a commutation step with `duty` on the high phase
+        pub fn six_step(&mut self, phases: [Phase; 3], duty: u16) {
+            let duty = duty.min(self.max_duty) as u32;
+            // PWM mode 1 for the driven phase, forced inactive (low side on) for the sinking phase
+            let mode = |phase: Phase| match phase {
+                Phase::High => 0b110,
+                Phase::Low | Phase::Float => 0b100,
+            };
+            let enabled = |phase: Phase| phase != Phase::Float;
+            self.tim.ccr1.write(|w| unsafe { w.bits(duty) });
+            self.tim.ccr2.write(|w| unsafe { w.bits(duty) });
+            self.tim.ccr3.write(|w| unsafe { w.bits(duty) });
+            self.tim.ccmr1_output().modify(|_, w| unsafe {
+                w.oc1m().bits(mode(phases[0])).oc2m().bits(mode(phases[1]))
+            });
+            self.tim
+                .ccmr2_output()
+                .modify(|_, w| unsafe { w.oc3m().bits(mode(phases[2])) });
+            self.tim.ccer.modify(|_, w| {
+                w.cc1e()
+                    .bit(enabled(phases[0]))
+                    .cc1ne()
+                    .bit(enabled(phases[0]))
+                    .cc2e()
+                    .bit(enabled(phases[1]))
+                    .cc2ne()
+                    .bit(enabled(phases[1]))
+                    .cc3e()
+                    .bit(enabled(phases[2]))
+                    .cc3ne()
+                    .bit(enabled(phases[2]))
+            });
+            // Commutation event loads the preloaded bits at once
+            self.tim.egr.write(|w| w.comg().set_bit());
+        }
+
+        /// Sets Q15 duties on all three phases for sinusoidal drive
+        pub fn set_duties(&mut self, duties: [u16; 3]) {
+            let max = self.max_duty as u32;
+            let ccr = |d: u16| d as u32 * max >> 15;
+            self.tim.ccr1.write(|w| unsafe { w.bits(ccr(duties[0])) });
+            self.tim.ccr2.write(|w| unsafe { w.bits(ccr(duties[1])) });
+            self.tim.ccr3.write(|w| unsafe { w.bits(ccr(duties[2])) });
+        }
+
+        /// Drives all three phases with PWM, for `set_duties`
+        pub fn sinusoidal(&mut self) {
+            self.six_step([Phase::High; 3], self.max_duty / 2);
+        }
+    }
+
+    /// Injected group of ADC1: PA0 (IN0) and PA1 (IN1) on TIM1 CC4, with JEOC interrupt
+    pub fn injected_currents(adc: ADC1) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.adc1en().set_bit());
+        // The ADC clock must stay under 36 MHz, PCLK2 / 4 is 21 MHz at 84 MHz
+        let common = unsafe { &(*ADC_COMMON::ptr()) };
+        common.ccr.modify(|_, w| w.adcpre().div4());
+
+        // Two conversions start at JSQ3: JL = 1, JSQ3 = IN0, JSQ4 = IN1
+        adc.jsqr.write(|w| unsafe { w.bits(1 << 20 | 1 << 15) });
+        // 15 cycles is short enough for 20 kHz PWM
+        adc.smpr2
+            .modify(|r, w| unsafe { w.bits(r.bits() & !0b111_111 | 0b001_001) });
+        adc.cr1.modify(|_, w| w.scan().set_bit().jeocie().set_bit());
+        // JEXTSEL 0000 is TIM1 CC4, rising edge
+        adc.cr2.modify(|_, w| unsafe {
+            w.jexten()
+                .bits(0b01)
+                .jextsel()
+                .bits(0b0000)
+                .adon()
+                .set_bit()
+        });
+    }
+
+    /// Reads the injected results and clears JEOC
+    pub fn read_currents() -> [u16; 2] {
+        let adc = unsafe { &(*ADC1::ptr()) };
+        let currents = [adc.jdr1.read().bits() as u16, adc.jdr2.read().bits() as u16];
+        adc.sr.modify(|_, w| w.jeoc().clear_bit());
+        currents
+    }
+}
@@ -0,0 +1,316 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::peripheral;
+use cortex_m_rt::entry;
+use profile::{Itm, Profiler, Stopwatch};
+use stm32f4xx_hal::{
+    adc::{
+        config::AdcConfig, config::Eoc, config::ExternalTrigger, config::SampleTime,
+        config::Sequence, config::TriggerMode, Adc,
+    },
+    prelude::*,
+    pwm,
+    serial::{config::Config, Event, Rx, Serial},
+    stm32,
+    stm32::{interrupt, USART3},
+};
+
+static PROFILER: Mutex<RefCell<Profiler>> = Mutex::new(RefCell::new(Profiler::new()));
+static ADC: Mutex<RefCell<Option<Adc<stm32::ADC1>>>> = Mutex::new(RefCell::new(None));
+static RX: Mutex<RefCell<Option<Rx<USART3>>>> = Mutex::new(RefCell::new(None));
+static LAST_SAMPLE: Mutex<Cell<u16>> = Mutex::new(Cell::new(0));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[interrupt]
+fn ADC() {
+    let watch = Stopwatch::start();
+    free(|cs| {
+        if let Some(ref mut adc) = ADC.borrow(cs).borrow_mut().deref_mut() {
+            let sample = adc.current_sample();
+            LAST_SAMPLE.borrow(cs).set(sample);
+        }
+        PROFILER
+            .borrow(cs)
+            .borrow_mut()
+            .record("ADC", watch.elapsed());
+    });
+}
+
+// Any byte received resets the statistics
+#[interrupt]
+fn USART3() {
+    let watch = Stopwatch::start();
+    free(|cs| {
+        let mut profiler = PROFILER.borrow(cs).borrow_mut();
+        if let Some(ref mut rx) = RX.borrow(cs).borrow_mut().deref_mut() {
+            if rx.read().is_ok() {
+                profiler.reset();
+            }
+        }
+        profiler.record("USART3", watch.elapsed());
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    // Stopwatches read CYCCNT
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(84.mhz()).freeze();
+
+    // Cost of an empty measurement, taken off every sample
+    let overhead = free(|cs| {
+        let mut profiler = PROFILER.borrow(cs).borrow_mut();
+        profiler.calibrate();
+        profiler.overhead()
+    });
+    writeln!(Itm(itm()), "measurement overhead: {} cycles", overhead).ok();
+
+    // ADC on PA3 triggered at 1 kHz by TIM1 CH1
+    let gpioa = dp.GPIOA.split();
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let mut pwm = pwm::tim1(dp.TIM1, pa8, clocks, 1.khz());
+    pwm.set_duty(pwm.get_max_duty() / 2);
+    pwm.enable();
+
+    let config = AdcConfig::default()
+        .end_of_conversion_interrupt(Eoc::Conversion)
+        .external_trigger(TriggerMode::RisingEdge, ExternalTrigger::Tim_1_cc_1);
+    let mut adc = Adc::adc1(dp.ADC1, true, config);
+    let pa3 = gpioa.pa3.into_analog();
+    adc.configure_channel(&pa3, Sequence::One, SampleTime::Cycles_112);
+    adc.enable();
+
+    // Report over USART3 (ST-LINK virtual COM port) as well as ITM
+    let gpiod = dp.GPIOD.split();
+    let tx = gpiod.pd8.into_alternate_af7();
+    let rx = gpiod.pd9.into_alternate_af7();
+    let mut serial = Serial::usart3(
+        dp.USART3,
+        (tx, rx),
+        Config::default().baudrate(115_200.bps()),
+        clocks,
+    )
+    .unwrap();
+    serial.listen(Event::Rxne);
+    let (mut tx, rx) = serial.split();
+
+    free(|cs| {
+        ADC.borrow(cs).replace(Some(adc));
+        RX.borrow(cs).replace(Some(rx));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::Interrupt::ADC);
+    stm32::NVIC::unpend(stm32::Interrupt::USART3);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::ADC);
+        stm32::NVIC::unmask(stm32::Interrupt::USART3);
+    }
+
+    let sysclk = clocks.sysclk().0;
+    loop {
+        cortex_m::asm::delay(sysclk);
+
+        // Sections can be measured in thread mode as well
+        let millivolts = free(|cs| {
+            PROFILER.borrow(cs).borrow_mut().measure("scale", || {
+                let sample = LAST_SAMPLE.borrow(cs).get();
+                sample as u32 * 3_300 / 4_095
+            })
+        });
+
+        // Copy the table out, printing it must not hold off the interrupts
+        let snapshot = free(|cs| PROFILER.borrow(cs).borrow().clone());
+        writeln!(Itm(itm()), "PA3: {} mV", millivolts).ok();
+        snapshot.report(&mut Itm(itm()), sysclk).ok();
+        snapshot.report(&mut tx, sysclk).ok();
+    }
+}
+
+mod profile {
+    use core::fmt;
+    use cortex_m::peripheral::{itm::Stim, DWT};
+
+    /// Sections that can be recorded
+    pub const CAPACITY: usize = 8;
+
+    /// Measures cycles with the DWT cycle counter, which has to be running.
+    /// CYCCNT wraps every 2^32 cycles (51 s at 84 MHz), longer spans read short.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Stopwatch {
+        start: u32,
+    }
+
+    impl Stopwatch {
+        pub fn start() -> Self {
+            Stopwatch {
+                start: DWT::get_cycle_count(),
+            }
+        }
+
+        /// Cycles since `start`
+        pub fn elapsed(&self) -> u32 {
+            DWT::get_cycle_count().wrapping_sub(self.start)
+        }
+    }
+
+    /// Cycles to nanoseconds at `sysclk` Hz
+    pub fn nanos(cycles: u64, sysclk: u32) -> u64 {
+        (cycles as u128 * 1_000_000_000 / sysclk.max(1) as u128) as u64
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Stats {
+        pub count: u32,
+        pub min: u32,
+        pub max: u32,
+        pub total: u64,
+    }
+
+    impl Stats {
+        pub const fn new() -> Self {
+            Stats {
+                count: 0,
+                min: u32::MAX,
+                max: 0,
+                total: 0,
+            }
+        }
+
+        pub fn record(&mut self, cycles: u32) {
+            self.count = self.count.saturating_add(1);
+            self.min = self.min.min(cycles);
+            self.max = self.max.max(cycles);
+            self.total += cycles as u64;
+        }
+
+        /// Mean cycles, 0 before the first sample
+        pub fn avg(&self) -> u32 {
+            if self.count == 0 {
+                0
+            } else {
+                (self.total / self.count as u64) as u32
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Section {
+        name: &'static str,
+        stats: Stats,
+    }
+
+    /// Min/max/avg cycles per named section
+    #[derive(Debug, Clone)]
+    pub struct Profiler {
+        sections: [Option<Section>; CAPACITY],
+        /// Cycles an empty measurement takes
+        overhead: u32,
+    }
+
+    impl Profiler {
+        pub const fn new() -> Self {
+            Profiler {
+                sections: [None; CAPACITY],
+                overhead: 0,
+            }
+        }
+
+        /// Measures an empty section, the result is taken off every recording
+        pub fn calibrate(&mut self) {
+            let watch = Stopwatch::start();
+            self.overhead = watch.elapsed();
+        }
+
+        pub fn overhead(&self) -> u32 {
+            self.overhead
+        }
+
+        /// Adds a measurement to `name`, which is created on first use.
+        /// False when the table is full.
+        pub fn record(&mut self, name: &'static str, cycles: u32) -> bool {
+            let cycles = cycles.saturating_sub(self.overhead);
+            let position = self
+                .sections
+                .iter()
+                .position(|s| s.map_or(true, |s| s.name == name));
+            match position {
+                Some(i) => {
+                    let section = self.sections[i].get_or_insert(Section {
+                        name,
+                        stats: Stats::new(),
+                    });
+                    section.stats.record(cycles);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        /// Runs `f` and records how long it took
+        pub fn measure<T, F>(&mut self, name: &'static str, f: F) -> T
+        where
+            F: FnOnce() -> T,
+        {
+            let watch = Stopwatch::start();
+            let result = f();
+            self.record(name, watch.elapsed());
+            result
+        }
+
+        /// Forgets every section
+        pub fn reset(&mut self) {
+            self.sections = [None; CAPACITY];
+        }
+
+        /// Writes one line per section, times in µs at `sysclk` Hz
+        pub fn report<W: fmt::Write>(&self, w: &mut W, sysclk: u32) -> fmt::Result {
+            write!(
+                w,
+                "{:<10} {:>8} {:>10} {:>10} {:>10}\r\n",
+                "section", "count", "min us", "avg us", "max us"
+            )?;
+            for section in self.sections.iter().filter_map(|s| s.as_ref()) {
+                let stats = &section.stats;
+                write!(w, "{:<10} {:>8}", section.name, stats.count)?;
+                for &cycles in [stats.min, stats.avg(), stats.max].iter() {
+                    let ns = nanos(cycles as u64, sysclk);
+                    write!(w, " {:>6}.{:03}", ns / 1_000, ns % 1_000)?;
+                }
+                write!(w, "\r\n")?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Default for Profiler {
+        fn default() -> Self {
+            Profiler::new()
+        }
+    }
+
+    /// `fmt::Write` on an ITM stimulus port
+    pub struct Itm<'a>(pub &'a mut Stim);
+
+    impl<'a> fmt::Write for Itm<'a> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            cortex_m::itm::write_str(self.0, s);
+            Ok(())
+        }
+    }
+}
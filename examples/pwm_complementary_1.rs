@@ -0,0 +1,372 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use advanced::{Alignment, BreakPolarity, ComplementaryPwm, Config};
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::{
+    interrupt::{free, Mutex},
+    {iprintln, peripheral},
+};
+use cortex_m_rt::entry;
+use stm32f4xx_hal::{prelude::*, stm32, stm32::interrupt};
+
+#[allow(dead_code)]
+#[path = "shared/dead_time.rs"]
+mod dead_time;
+
+static PWM: Mutex<RefCell<Option<ComplementaryPwm>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+// Break input. The outputs are already off in hardware when we get here.
+#[interrupt]
+fn TIM1_BRK_TIM9() {
+    free(|cs| {
+        if let Some(ref mut pwm) = PWM.borrow(cs).borrow_mut().deref_mut() {
+            if pwm.is_faulted() {
+                // BIF is set again as long as BKIN stays active, so stop listening
+                // until `enable_outputs` re-arms the break interrupt
+                pwm.acknowledge_break();
+                iprintln!(itm(), "[PWM] Fault: outputs disabled");
+            }
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(84.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+
+    // CH1 on PA8, CH1N on PB13, BKIN on PB12
+    let ch1 = gpioa.pa8.into_alternate_af1();
+    let ch1n = gpiob.pb13.into_alternate_af1();
+    let bkin = gpiob.pb12.into_alternate_af1();
+
+    // 20 kHz center-aligned, 500 ns dead time, driver fault pulls BKIN low
+    let config = Config::new(20_000)
+        .alignment(Alignment::Center)
+        .dead_time_ns(500)
+        .break_input(BreakPolarity::ActiveLow);
+    let mut pwm = ComplementaryPwm::tim1(dp.TIM1, clocks, config);
+    let channel = pwm.bind(ch1, ch1n);
+    pwm.bind_break(bkin);
+    pwm.set_duty(&channel, pwm.get_max_duty() / 2);
+    pwm.enable(&channel);
+    pwm.enable_outputs();
+
+    free(|cs| {
+        PWM.borrow(cs).replace(Some(pwm));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::TIM1_BRK_TIM9);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM1_BRK_TIM9);
+    }
+
+    // The user button re-arms the outputs after a fault
+    let button = gpioc.pc13.into_pull_down_input();
+    loop {
+        if button.is_high().unwrap() {
+            free(|cs| {
+                if let Some(ref mut pwm) = PWM.borrow(cs).borrow_mut().deref_mut() {
+                    pwm.enable_outputs();
+                }
+            });
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod advanced {
+    use crate::dead_time::dead_time_bits;
+    use core::marker::PhantomData;
+    use stm32f4xx_hal::gpio::{gpioa, gpiob, gpioe, Alternate, AF1};
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{RCC, TIM1};
+
+    /// Output pins for a TIM1 channel
+    pub trait OutputPin<CH> {}
+    /// Complementary output pins for a TIM1 channel
+    pub trait ComplementaryPin<CH> {}
+    /// Break input pins of TIM1
+    pub trait BreakPin {}
+
+    /// Channel markers for pin traits
+    pub trait ChannelMarker {
+        /// Channel number, from 1
+        const NUMBER: u8;
+    }
+    pub struct Ch1;
+    pub struct Ch2;
+    pub struct Ch3;
+
+    impl ChannelMarker for Ch1 {
+        const NUMBER: u8 = 1;
+    }
+    impl ChannelMarker for Ch2 {
+        const NUMBER: u8 = 2;
+    }
+    impl ChannelMarker for Ch3 {
+        const NUMBER: u8 = 3;
+    }
+
+    macro_rules! pins {
+        ($($TRAIT:ident<$CH:ident>: [$($PIN:ty),+],)+) => {
+            $($(impl $TRAIT<$CH> for $PIN {})+)+
+        };
+    }
+
+    pins! {
+        OutputPin<Ch1>: [gpioa::PA8<Alternate<AF1>>, gpioe::PE9<Alternate<AF1>>],
+        OutputPin<Ch2>: [gpioa::PA9<Alternate<AF1>>, gpioe::PE11<Alternate<AF1>>],
+        OutputPin<Ch3>: [gpioa::PA10<Alternate<AF1>>, gpioe::PE13<Alternate<AF1>>],
+        ComplementaryPin<Ch1>: [gpioa::PA7<Alternate<AF1>>, gpiob::PB13<Alternate<AF1>>, gpioe::PE8<Alternate<AF1>>],
+        ComplementaryPin<Ch2>: [gpiob::PB0<Alternate<AF1>>, gpiob::PB14<Alternate<AF1>>, gpioe::PE10<Alternate<AF1>>],
+        ComplementaryPin<Ch3>: [gpiob::PB1<Alternate<AF1>>, gpiob::PB15<Alternate<AF1>>, gpioe::PE12<Alternate<AF1>>],
+    }
+
+    impl BreakPin for gpioa::PA6<Alternate<AF1>> {}
+    impl BreakPin for gpiob::PB12<Alternate<AF1>> {}
+    impl BreakPin for gpioe::PE15<Alternate<AF1>> {}
+
+    /// A channel with complementary outputs. Only `ComplementaryPwm::bind` hands these
+    /// out, in exchange for the channel's pins.
+    #[derive(Debug)]
+    pub struct Channel<CH> {
+        _channel: PhantomData<CH>,
+    }
+
+    /// Counter mode
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Alignment {
+        /// Up-counting, edge-aligned
+        Edge,
+        /// Up/down-counting, symmetric pulses centered on the counter underflow
+        Center,
+    }
+
+    /// Active level of BKIN
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum BreakPolarity {
+        ActiveLow,
+        ActiveHigh,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Config {
+        frequency: u32,
+        alignment: Alignment,
+        dead_time_ns: u32,
+        break_input: Option<BreakPolarity>,
+    }
+
+    impl Config {
+        /// PWM frequency in Hz
+        pub fn new(frequency: u32) -> Self {
+            Config {
+                frequency,
+                alignment: Alignment::Edge,
+                dead_time_ns: 0,
+                break_input: None,
+            }
+        }
+        pub fn alignment(self, alignment: Alignment) -> Self {
+            Config { alignment, ..self }
+        }
+        /// Delay between one output turning off and its complement turning on
+        pub fn dead_time_ns(self, dead_time_ns: u32) -> Self {
+            Config {
+                dead_time_ns,
+                ..self
+            }
+        }
+        /// Shut all outputs down in hardware when BKIN becomes active
+        pub fn break_input(self, polarity: BreakPolarity) -> Self {
+            Config {
+                break_input: Some(polarity),
+                ..self
+            }
+        }
+    }
+
+    /// TIM1 advanced-timer PWM with complementary outputs, dead-time and break input
+    pub struct ComplementaryPwm {
+        tim: TIM1,
+        max_duty: u16,
+        break_input: bool,
+    }
+
+    impl ComplementaryPwm {
+        pub fn tim1(tim: TIM1, clocks: Clocks, config: Config) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb2enr.modify(|_, w| w.tim1en().set_bit());
+            rcc.apb2rstr.modify(|_, w| w.tim1rst().set_bit());
+            rcc.apb2rstr.modify(|_, w| w.tim1rst().clear_bit());
+
+            // APB2 timers run at twice PCLK2 when the APB2 prescaler is not 1
+            let clock = if clocks.ppre2() == 1 {
+                clocks.pclk2().0
+            } else {
+                clocks.pclk2().0 * 2
+            };
+
+            // Center-aligned counts up and down, so one period takes 2 * ARR ticks.
+            // Edge-aligned counts 0..=ARR, ARR + 1 ticks.
+            let ticks = match config.alignment {
+                Alignment::Edge => clock / config.frequency,
+                Alignment::Center => clock / config.frequency / 2,
+            };
+            assert!(ticks >= 2, "PWM frequency too high for the timer clock");
+            // Smallest prescaler that fits the 16 bit counter
+            let psc = ticks / (1 << 16);
+            let period = ticks / (psc + 1);
+            let arr = match config.alignment {
+                Alignment::Edge => period - 1,
+                Alignment::Center => period,
+            };
+            tim.psc.write(|w| w.psc().bits(psc as u16));
+            tim.arr.write(|w| unsafe { w.bits(arr) });
+
+            let cms = match config.alignment {
+                Alignment::Edge => 0b00,
+                // Compare flags set while counting up and down
+                Alignment::Center => 0b11,
+            };
+            tim.cr1
+                .write(|w| unsafe { w.cms().bits(cms).arpe().set_bit() });
+
+            // Dead-time is counted in t_DTS = 1 / timer clock (CKD = 0)
+            let dtg = dead_time_bits(config.dead_time_ns, clock).expect("Dead-time too long");
+            tim.bdtr.write(|w| {
+                let w = unsafe { w.dtg().bits(dtg) };
+                // Off-state outputs are driven to their idle level instead of floating
+                let w = w.ossr().set_bit().ossi().set_bit();
+                match config.break_input {
+                    Some(BreakPolarity::ActiveLow) => w.bke().set_bit().bkp().clear_bit(),
+                    Some(BreakPolarity::ActiveHigh) => w.bke().set_bit().bkp().set_bit(),
+                    None => w.bke().clear_bit(),
+                }
+            });
+
+            // Load the registers and start counting. Outputs stay off until MOE is set.
+            tim.egr.write(|w| w.ug().set_bit());
+            tim.sr.modify(|_, w| w.uif().clear_bit());
+            tim.cr1.modify(|_, w| w.cen().set_bit());
+
+            ComplementaryPwm {
+                tim,
+                // Full on at CCR = ARR + 1 edge-aligned, CCR = ARR center-aligned
+                max_duty: period as u16,
+                break_input: config.break_input.is_some(),
+            }
+        }
+
+        /// Takes the output pin pair of a channel and returns the channel
+        pub fn bind<CH, P, N>(&mut self, _pin: P, _complementary: N) -> Channel<CH>
+        where
+            CH: ChannelMarker,
+            P: OutputPin<CH>,
+            N: ComplementaryPin<CH>,
+        {
+            Channel {
+                _channel: PhantomData,
+            }
+        }
+
+        /// Takes the BKIN pin
+        pub fn bind_break<P: BreakPin>(&mut self, _pin: P) {}
+
+        pub fn get_max_duty(&self) -> u16 {
+            self.max_duty
+        }
+
+        /// Sets the duty of the main output. The complementary output is its inverse minus dead-time.
+        pub fn set_duty<CH: ChannelMarker>(&mut self, _channel: &Channel<CH>, duty: u16) {
+            let duty = duty.min(self.max_duty) as u32;
+            match CH::NUMBER {
+                1 => self.tim.ccr1.write(|w| unsafe { w.bits(duty) }),
+                2 => self.tim.ccr2.write(|w| unsafe { w.bits(duty) }),
+                _ => self.tim.ccr3.write(|w| unsafe { w.bits(duty) }),
+            }
+        }
+
+        /// Enables both outputs of a channel in PWM mode 1
+        pub fn enable<CH: ChannelMarker>(&mut self, _channel: &Channel<CH>) {
+            match CH::NUMBER {
+                1 => self
+                    .tim
+                    .ccmr1_output()
+                    .modify(|_, w| w.oc1pe().set_bit().oc1m().pwm_mode1()),
+                2 => self
+                    .tim
+                    .ccmr1_output()
+                    .modify(|_, w| w.oc2pe().set_bit().oc2m().pwm_mode1()),
+                _ => self
+                    .tim
+                    .ccmr2_output()
+                    .modify(|_, w| w.oc3pe().set_bit().oc3m().pwm_mode1()),
+            }
+            self.tim.ccer.modify(|_, w| match CH::NUMBER {
+                1 => w.cc1e().set_bit().cc1ne().set_bit(),
+                2 => w.cc2e().set_bit().cc2ne().set_bit(),
+                _ => w.cc3e().set_bit().cc3ne().set_bit(),
+            });
+        }
+
+        /// Disables both outputs of a channel
+        pub fn disable<CH: ChannelMarker>(&mut self, _channel: &Channel<CH>) {
+            self.tim.ccer.modify(|_, w| match CH::NUMBER {
+                1 => w.cc1e().clear_bit().cc1ne().clear_bit(),
+                2 => w.cc2e().clear_bit().cc2ne().clear_bit(),
+                _ => w.cc3e().clear_bit().cc3ne().clear_bit(),
+            });
+        }
+
+        /// Main output enable, cleared by hardware on break. Re-arms the break
+        /// interrupt. Does nothing while the break input is still active.
+        pub fn enable_outputs(&mut self) {
+            // BIF is set again right away if BKIN is still active
+            self.clear_break_flag();
+            if self.is_faulted() {
+                return;
+            }
+            self.tim.bdtr.modify(|_, w| w.moe().set_bit());
+            if self.break_input {
+                self.tim.dier.modify(|_, w| w.bie().set_bit());
+            }
+        }
+
+        /// Turns every output off at once
+        pub fn disable_outputs(&mut self) {
+            self.tim.bdtr.modify(|_, w| w.moe().clear_bit());
+        }
+
+        /// True once the break input has been active, until the flag is cleared
+        pub fn is_faulted(&self) -> bool {
+            self.tim.sr.read().bif().bit_is_set()
+        }
+
+        /// Disables the break interrupt and clears the flag. Call from the break
+        /// interrupt, `enable_outputs` enables it again once the fault is gone.
+        pub fn acknowledge_break(&mut self) {
+            self.tim.dier.modify(|_, w| w.bie().clear_bit());
+            self.clear_break_flag();
+        }
+
+        /// Clears the break flag. Outputs stay off until `enable_outputs`.
+        pub fn clear_break_flag(&mut self) {
+            self.tim.sr.modify(|_, w| w.bif().clear_bit());
+        }
+    }
+}
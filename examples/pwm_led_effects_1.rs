@@ -0,0 +1,174 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use effects::{Color, Effect, Engine};
+use rgb::Rgb;
+use stm32f4xx_hal::{
+    prelude::*,
+    pwm, stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+
+#[allow(dead_code)]
+#[path = "shared/curve.rs"]
+mod curve;
+
+#[allow(dead_code)]
+#[path = "shared/led_effects.rs"]
+mod effects;
+
+/// Effect frame rate
+const FRAME_HZ: u32 = 100;
+
+type Led = Rgb<
+    pwm::PwmChannels<stm32::TIM3, pwm::C1>,
+    pwm::PwmChannels<stm32::TIM3, pwm::C2>,
+    pwm::PwmChannels<stm32::TIM3, pwm::C3>,
+>;
+
+struct Lights {
+    led: Led,
+    engine: Engine,
+}
+
+static LIGHTS: Mutex<RefCell<Option<Lights>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+// Frame scheduler. Renders one frame of the running effect.
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(Event::TimeOut);
+        }
+        if let Some(ref mut lights) = LIGHTS.borrow(cs).borrow_mut().deref_mut() {
+            let color = lights.engine.tick();
+            lights.led.set(color);
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+    let gpiob = dp.GPIOB.split();
+
+    // Common cathode RGB LED on PA6 (red), PA7 (green), PB0 (blue), TIM3 CH1-CH3.
+    // PB0 also drives the green user LED LD1 on the Nucleo.
+    let pa6 = gpioa.pa6.into_alternate_af2();
+    let pa7 = gpioa.pa7.into_alternate_af2();
+    let pb0 = gpiob.pb0.into_alternate_af2();
+    let (red, green, blue) = pwm::tim3(dp.TIM3, (pa6, pa7, pb0), clocks, 1.khz());
+    let led = Rgb::new(red, green, blue);
+
+    // Set up the frame timer
+    let mut timer = Timer::tim2(dp.TIM2, FRAME_HZ.hz(), clocks);
+    timer.listen(Event::TimeOut);
+
+    free(|cs| {
+        LIGHTS.borrow(cs).replace(Some(Lights {
+            led,
+            engine: Engine::new(),
+        }));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+    }
+
+    // Show reel, each effect runs for 5 seconds. Frame counts are at FRAME_HZ.
+    let reel = [
+        Effect::Fade {
+            to: Color::RED,
+            frames: 100,
+        },
+        Effect::Fade {
+            to: Color::new(255, 80, 0),
+            frames: 200,
+        },
+        Effect::Breathe {
+            color: Color::CYAN,
+            period: 300,
+        },
+        Effect::Blink {
+            color: Color::WHITE,
+            on: 10,
+            off: 40,
+        },
+        Effect::Rainbow { period: 500 },
+        Effect::Fade {
+            to: Color::BLACK,
+            frames: 100,
+        },
+    ];
+    loop {
+        for effect in reel.iter() {
+            free(|cs| {
+                if let Some(ref mut lights) = LIGHTS.borrow(cs).borrow_mut().deref_mut() {
+                    lights.engine.start(*effect);
+                }
+            });
+            cortex_m::asm::delay(48_000_000 * 5);
+        }
+    }
+}
+
+mod rgb {
+    use crate::curve::{Curve, ADC_MAX, GAMMA_2_2, OUT_MAX};
+    use crate::effects::Color;
+    use stm32f4xx_hal::hal::PwmPin;
+
+    /// RGB LED on three PWM channels
+    pub struct Rgb<R, G, B> {
+        red: R,
+        green: G,
+        blue: B,
+    }
+
+    impl<R, G, B> Rgb<R, G, B>
+    where
+        R: PwmPin<Duty = u16>,
+        G: PwmPin<Duty = u16>,
+        B: PwmPin<Duty = u16>,
+    {
+        /// Enables the channels with the LED off
+        pub fn new(mut red: R, mut green: G, mut blue: B) -> Self {
+            red.set_duty(0);
+            green.set_duty(0);
+            blue.set_duty(0);
+            red.enable();
+            green.enable();
+            blue.enable();
+            Rgb { red, green, blue }
+        }
+
+        /// Shows `color` with gamma correction
+        pub fn set(&mut self, color: Color) {
+            self.red.set_duty(duty(color.r, self.red.get_max_duty()));
+            self.green
+                .set_duty(duty(color.g, self.green.get_max_duty()));
+            self.blue.set_duty(duty(color.b, self.blue.get_max_duty()));
+        }
+    }
+
+    /// Perceived brightness `level` to duty, through the gamma 2.2 curve
+    fn duty(level: u8, max_duty: u16) -> u16 {
+        // 0..=255 onto the 12 bit input range of the curve
+        let x = level as u32 * ADC_MAX as u32 / 255;
+        let y = Curve::Table(&GAMMA_2_2).eval(x as u16) as u32;
+        (y * max_duty as u32 / OUT_MAX as u32) as u16
+    }
+}
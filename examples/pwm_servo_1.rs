@@ -0,0 +1,279 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use servo::{Calibration, Servo};
+use stm32f4xx_hal::{
+    prelude::*,
+    pwm, stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+
+/// Servo update rate, one step per PWM frame
+const TICK_HZ: u32 = 50;
+
+/// Servos on TIM1 CH1/CH2, TIM3 CH1 and TIM4 CH1
+struct Servos {
+    pan: Servo<pwm::PwmChannels<stm32::TIM1, pwm::C1>>,
+    tilt: Servo<pwm::PwmChannels<stm32::TIM1, pwm::C2>>,
+    gripper: Servo<pwm::PwmChannels<stm32::TIM3, pwm::C1>>,
+    wrist: Servo<pwm::PwmChannels<stm32::TIM4, pwm::C1>>,
+}
+
+impl Servos {
+    fn is_moving(&self) -> bool {
+        self.pan.is_moving()
+            || self.tilt.is_moving()
+            || self.gripper.is_moving()
+            || self.wrist.is_moving()
+    }
+}
+
+static SERVOS: Mutex<RefCell<Option<Servos>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(Event::TimeOut);
+        }
+        if let Some(ref mut servos) = SERVOS.borrow(cs).borrow_mut().deref_mut() {
+            let dt_ms = 1_000 / TICK_HZ;
+            servos.pan.update(dt_ms);
+            servos.tilt.update(dt_ms);
+            servos.gripper.update(dt_ms);
+            servos.wrist.update(dt_ms);
+        }
+    });
+}
+
+/// Counter rate of a timer clocked at `timer_clock` with prescaler register `psc`
+fn counter_hz(timer_clock: u32, psc: u16) -> u32 {
+    timer_clock / (psc as u32 + 1)
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+    let gpiod = dp.GPIOD.split();
+
+    // 50 Hz PWM on PA8, PA9 (TIM1), PA6 (TIM3) and PD12 (TIM4)
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let pa9 = gpioa.pa9.into_alternate_af1();
+    let (pan, tilt) = pwm::tim1(dp.TIM1, (pa8, pa9), clocks, 50.hz());
+    let pa6 = gpioa.pa6.into_alternate_af2();
+    let gripper = pwm::tim3(dp.TIM3, pa6, clocks, 50.hz());
+    let pd12 = gpiod.pd12.into_alternate_af2();
+    let wrist = pwm::tim4(dp.TIM4, pd12, clocks, 50.hz());
+
+    // Timers on APB1/APB2 run at twice PCLK1/PCLK2 when the APB prescaler is not 1
+    let apb1_timers = if clocks.ppre1() == 1 {
+        clocks.pclk1().0
+    } else {
+        clocks.pclk1().0 * 2
+    };
+    let apb2_timers = if clocks.ppre2() == 1 {
+        clocks.pclk2().0
+    } else {
+        clocks.pclk2().0 * 2
+    };
+    // The HAL picks the prescalers, read back what it chose
+    let (tim1_hz, tim3_hz, tim4_hz) = unsafe {
+        (
+            counter_hz(apb2_timers, (*stm32::TIM1::ptr()).psc.read().psc().bits()),
+            counter_hz(apb1_timers, (*stm32::TIM3::ptr()).psc.read().psc().bits()),
+            counter_hz(apb1_timers, (*stm32::TIM4::ptr()).psc.read().psc().bits()),
+        )
+    };
+
+    // Per-servo calibration
+    let standard = Calibration::default();
+    let servos = Servos {
+        pan: Servo::new(pan, tim1_hz, standard).speed(90),
+        tilt: Servo::new(tilt, tim1_hz, Calibration::new(900, 2100, -60, 60)).speed(45),
+        gripper: Servo::new(gripper, tim3_hz, Calibration::new(1200, 1800, 0, 100)),
+        wrist: Servo::new(wrist, tim4_hz, Calibration::new(500, 2500, 0, 270)).speed(180),
+    };
+
+    // Set up the update timer
+    let mut timer = Timer::tim2(dp.TIM2, TICK_HZ.hz(), clocks);
+    timer.listen(Event::TimeOut);
+
+    free(|cs| {
+        SERVOS.borrow(cs).replace(Some(servos));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+    }
+
+    let mut forward = true;
+    loop {
+        // Swing everything end to end. The moves are speed limited by the servos.
+        free(|cs| {
+            if let Some(ref mut servos) = SERVOS.borrow(cs).borrow_mut().deref_mut() {
+                if forward {
+                    servos.pan.move_to(180);
+                    servos.tilt.move_to(60);
+                    servos.gripper.move_to(100);
+                    servos.wrist.move_to(270);
+                } else {
+                    servos.pan.move_to(0);
+                    servos.tilt.move_to(-60);
+                    servos.gripper.move_to(0);
+                    servos.wrist.move_to(0);
+                }
+            }
+        });
+        forward = !forward;
+        // Wait for the slowest servo, then hold the position for a second
+        while free(|cs| {
+            SERVOS
+                .borrow(cs)
+                .borrow()
+                .as_ref()
+                .is_some_and(Servos::is_moving)
+        }) {}
+        cortex_m::asm::delay(48_000_000);
+    }
+}
+
+mod servo {
+    use stm32f4xx_hal::hal::PwmPin;
+
+    /// Pulse widths at the ends of the travel and the angles they correspond to
+    #[derive(Debug, Clone, Copy)]
+    pub struct Calibration {
+        pub min_us: u16,
+        pub max_us: u16,
+        pub min_angle: i16,
+        pub max_angle: i16,
+    }
+
+    impl Calibration {
+        pub fn new(min_us: u16, max_us: u16, min_angle: i16, max_angle: i16) -> Self {
+            assert!(min_us < max_us && min_angle < max_angle);
+            Calibration {
+                min_us,
+                max_us,
+                min_angle,
+                max_angle,
+            }
+        }
+
+        /// Pulse width for an angle in 1/1000 degree, clamped to the calibrated range
+        fn pulse_us_milli(&self, millidegrees: i32) -> u16 {
+            let min = self.min_angle as i32 * 1000;
+            let max = self.max_angle as i32 * 1000;
+            let offset = (millidegrees.max(min).min(max) - min) as i64;
+            let span_us = (self.max_us - self.min_us) as i64;
+            let span = (max - min) as i64;
+            self.min_us + ((offset * span_us + span / 2) / span) as u16
+        }
+    }
+
+    impl Default for Calibration {
+        /// 1 ms to 2 ms over 0° to 180°
+        fn default() -> Self {
+            Calibration::new(1000, 2000, 0, 180)
+        }
+    }
+
+    /// Hobby servo on a 50 Hz PWM channel
+    pub struct Servo<P> {
+        pwm: P,
+        /// Timer counter ticks per second, one duty step is one tick
+        counter_hz: u32,
+        calibration: Calibration,
+        /// Degrees per second, 0 means unlimited
+        speed: u16,
+        /// Current position in 1/1000 degree, for smooth slow moves
+        position: i32,
+        target: i32,
+    }
+
+    impl<P> Servo<P>
+    where
+        P: PwmPin<Duty = u16>,
+    {
+        /// Enables the channel and centers the servo.
+        /// `counter_hz` is the rate the channel's timer counts at, timer clock / (PSC + 1).
+        pub fn new(mut pwm: P, counter_hz: u32, calibration: Calibration) -> Self {
+            let center = (calibration.min_angle as i32 + calibration.max_angle as i32) / 2 * 1000;
+            pwm.enable();
+            let mut servo = Servo {
+                pwm,
+                counter_hz,
+                calibration,
+                speed: 0,
+                position: center,
+                target: center,
+            };
+            servo.apply();
+            servo
+        }
+
+        /// Limits moves to `degrees_per_second`
+        pub fn speed(self, degrees_per_second: u16) -> Self {
+            Servo {
+                speed: degrees_per_second,
+                ..self
+            }
+        }
+
+        /// Starts a move to `angle`. Immediate if the speed is unlimited.
+        pub fn move_to(&mut self, angle: i16) {
+            let angle = angle
+                .max(self.calibration.min_angle)
+                .min(self.calibration.max_angle);
+            self.target = angle as i32 * 1000;
+            if self.speed == 0 {
+                self.position = self.target;
+                self.apply();
+            }
+        }
+
+        /// Steps the move, call every `dt_ms`
+        pub fn update(&mut self, dt_ms: u32) {
+            if self.position == self.target {
+                return;
+            }
+            // 1/1000 degree per ms == degree per second
+            let step = self.speed as i32 * dt_ms as i32;
+            let delta = self.target - self.position;
+            self.position += delta.max(-step).min(step);
+            self.apply();
+        }
+
+        /// Sets the pulse width directly, in microseconds.
+        /// Pulses longer than the PWM period are held high.
+        pub fn set_pulse_us(&mut self, us: u16) {
+            let max_duty = self.pwm.get_max_duty() as u64;
+            let duty = (us as u64 * self.counter_hz as u64 + 500_000) / 1_000_000;
+            self.pwm.set_duty(duty.min(max_duty) as u16);
+        }
+
+        /// True while a move is in progress
+        pub fn is_moving(&self) -> bool {
+            self.position != self.target
+        }
+
+        fn apply(&mut self) {
+            let us = self.calibration.pulse_us_milli(self.position);
+            self.set_pulse_us(us);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+#![no_main]
+#![no_std]
+
+use rtic::cyccnt::U32Ext;
+extern crate panic_halt;
+extern crate stm32f4xx_hal as hal;
+use hal::{
+    gpio::gpiob::PB7,
+    gpio::{Output, PushPull},
+    prelude::*,
+};
+use tone::{Note, Player, Tone};
+
+/// System clock, also the CYCCNT rate
+const SYSCLK_HZ: u32 = 48_000_000;
+const CYCLES_PER_MS: u32 = SYSCLK_HZ / 1_000;
+/// Silence between notes so repeated notes are heard separately
+const GAP_MS: u32 = 20;
+
+/// Ode to Joy
+const MELODY: [Note; 15] = [
+    Note::new(tone::E, 5, 400),
+    Note::new(tone::E, 5, 400),
+    Note::new(tone::F, 5, 400),
+    Note::new(tone::G, 5, 400),
+    Note::new(tone::G, 5, 400),
+    Note::new(tone::F, 5, 400),
+    Note::new(tone::E, 5, 400),
+    Note::new(tone::D, 5, 400),
+    Note::new(tone::C, 5, 400),
+    Note::new(tone::C, 5, 400),
+    Note::new(tone::D, 5, 400),
+    Note::new(tone::E, 5, 400),
+    Note::new(tone::E, 5, 600),
+    Note::new(tone::D, 5, 200),
+    Note::new(tone::D, 5, 800),
+];
+
+#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        buzzer: Tone,
+        player: Player,
+        led: PB7<Output<PushPull>>,
+    }
+
+    #[init(spawn = [play, blink])]
+    fn init(mut cx: init::Context) -> init::LateResources {
+        // CYCCNT drives the schedule
+        cx.core.DCB.enable_trace();
+        cx.core.DWT.enable_cycle_counter();
+
+        let rcc = cx.device.RCC.constrain();
+        let clocks = rcc.cfgr.sysclk(SYSCLK_HZ.hz()).freeze();
+
+        // Piezo buzzer on PA6 (TIM3 CH1)
+        let gpioa = cx.device.GPIOA.split();
+        let pa6 = gpioa.pa6.into_alternate_af2();
+        let buzzer = Tone::tim3(cx.device.TIM3, pa6, clocks);
+
+        // Set up LED
+        let gpiob = cx.device.GPIOB.split();
+        let led = gpiob.pb7.into_push_pull_output();
+
+        cx.spawn.play().unwrap();
+        cx.spawn.blink().unwrap();
+
+        // Initialization of late resources
+        init::LateResources {
+            buzzer,
+            player: Player::new(&MELODY, true),
+            led,
+        }
+    }
+
+    // Sequencer. Starts a note and schedules itself at the end of it.
+    #[task(schedule = [play, rest], resources = [buzzer, player])]
+    fn play(cx: play::Context) {
+        match cx.resources.player.next_note() {
+            Some(note) => {
+                cx.resources.buzzer.play(note.centihertz());
+                let length = note.duration_ms().saturating_sub(GAP_MS);
+                cx.schedule
+                    .rest(cx.scheduled + (length * CYCLES_PER_MS).cycles())
+                    .unwrap();
+                cx.schedule
+                    .play(cx.scheduled + (note.duration_ms() * CYCLES_PER_MS).cycles())
+                    .unwrap();
+            }
+            None => cx.resources.buzzer.stop(),
+        }
+    }
+
+    #[task(resources = [buzzer])]
+    fn rest(cx: rest::Context) {
+        cx.resources.buzzer.stop();
+    }
+
+    // Runs alongside the melody
+    #[task(schedule = [blink], resources = [led])]
+    fn blink(cx: blink::Context) {
+        cx.resources.led.toggle().unwrap();
+        cx.schedule
+            .blink(cx.scheduled + (250 * CYCLES_PER_MS).cycles())
+            .unwrap();
+    }
+
+    // This is required for the software tasks
+    // This can be any interrupt not used by hardware
+    extern "C" {
+        fn TIM2();
+    }
+};
+
+#[allow(dead_code)]
+mod tone {
+    use stm32f4xx_hal::gpio::{gpioa::PA6, Alternate, AF2};
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{RCC, TIM3};
+
+    /// Timer counter rate. 1 MHz gives better than 0.1% pitch accuracy up to 1 kHz.
+    const COUNTER_HZ: u32 = 1_000_000;
+
+    /// Square wave generator with a runtime adjustable frequency
+    pub struct Tone {
+        tim: TIM3,
+        _pin: PA6<Alternate<AF2>>,
+    }
+
+    impl Tone {
+        pub fn tim3(tim: TIM3, pin: PA6<Alternate<AF2>>, clocks: Clocks) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr.modify(|_, w| w.tim3en().set_bit());
+
+            let clock = if clocks.ppre1() == 1 {
+                clocks.pclk1().0
+            } else {
+                clocks.pclk1().0 * 2
+            };
+            tim.cr1.modify(|_, w| w.cen().clear_bit());
+            tim.psc
+                .write(|w| w.psc().bits((clock / COUNTER_HZ - 1) as u16));
+            // The counter doesn't run with ARR = 0, `play` sets the real period
+            tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+            // PWM mode 1 with preload, so frequency changes land on a period boundary
+            tim.ccmr1_output()
+                .modify(|_, w| w.oc1pe().set_bit().oc1m().pwm_mode1());
+            tim.ccer.modify(|_, w| w.cc1e().set_bit());
+            tim.ccr1.write(|w| unsafe { w.bits(0) });
+            tim.cr1.modify(|_, w| w.arpe().set_bit());
+            // Load PSC, ARR and CCR1 now, without flagging an update
+            tim.cr1.modify(|_, w| w.urs().set_bit());
+            tim.egr.write(|w| w.ug().set_bit());
+            tim.cr1.modify(|_, w| w.urs().clear_bit().cen().set_bit());
+            Tone { tim, _pin: pin }
+        }
+
+        /// Plays a tone at `centihertz` / 100 Hz. 0 is silence.
+        pub fn play(&mut self, centihertz: u32) {
+            if centihertz == 0 {
+                self.stop();
+                return;
+            }
+            let period = (COUNTER_HZ as u64 * 100 / centihertz as u64)
+                .max(2)
+                .min(0x1_0000) as u32;
+            self.tim.arr.write(|w| unsafe { w.bits(period - 1) });
+            // 50% duty is the loudest for a piezo
+            self.tim.ccr1.write(|w| unsafe { w.bits(period / 2) });
+        }
+
+        /// Silence
+        pub fn stop(&mut self) {
+            self.tim.ccr1.write(|w| unsafe { w.bits(0) });
+        }
+    }
+
+    /// Semitones from C
+    pub const C: u8 = 0;
+    pub const CS: u8 = 1;
+    pub const D: u8 = 2;
+    pub const DS: u8 = 3;
+    pub const E: u8 = 4;
+    pub const F: u8 = 5;
+    pub const FS: u8 = 6;
+    pub const G: u8 = 7;
+    pub const GS: u8 = 8;
+    pub const A: u8 = 9;
+    pub const AS: u8 = 10;
+    pub const B: u8 = 11;
+    /// Rest instead of a pitch
+    pub const REST: u8 = 0xFF;
+
+    /// Octave 4 in centihertz, A4 = 440 Hz
+    const OCTAVE_4: [u32; 12] = [
+        26163, 27718, 29366, 31113, 32963, 34923, 36999, 39200, 41530, 44000, 46616, 49388,
+    ];
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Note {
+        pitch: u8,
+        octave: u8,
+        duration_ms: u32,
+    }
+
+    impl Note {
+        pub const fn new(pitch: u8, octave: u8, duration_ms: u32) -> Self {
+            Note {
+                pitch,
+                octave,
+                duration_ms,
+            }
+        }
+
+        pub const fn rest(duration_ms: u32) -> Self {
+            Note::new(REST, 0, duration_ms)
+        }
+
+        /// Frequency in centihertz, 0 for a rest
+        pub fn centihertz(&self) -> u32 {
+            if self.pitch >= 12 {
+                return 0;
+            }
+            let base = OCTAVE_4[self.pitch as usize];
+            if self.octave >= 4 {
+                base << (self.octave - 4)
+            } else {
+                base >> (4 - self.octave)
+            }
+        }
+
+        pub fn duration_ms(&self) -> u32 {
+            self.duration_ms
+        }
+    }
+
+    /// Steps through a melody
+    pub struct Player {
+        melody: &'static [Note],
+        index: usize,
+        repeat: bool,
+    }
+
+    impl Player {
+        pub fn new(melody: &'static [Note], repeat: bool) -> Self {
+            Player {
+                melody,
+                index: 0,
+                repeat,
+            }
+        }
+
+        /// Next note, or `None` at the end of a non-repeating melody
+        pub fn next_note(&mut self) -> Option<Note> {
+            if self.index >= self.melody.len() {
+                if !self.repeat || self.melody.is_empty() {
+                    return None;
+                }
+                self.index = 0;
+            }
+            let note = self.melody[self.index];
+            self.index += 1;
+            Some(note)
+        }
+
+        /// Starts over with another melody
+        pub fn load(&mut self, melody: &'static [Note]) {
+            self.melody = melody;
+            self.index = 0;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+#![no_main]
+#![no_std]
+
+use clock::{Duration, Instant};
+use cortex_m::{iprintln, peripheral};
+use rtic::cyccnt::U32Ext;
+extern crate panic_halt;
+extern crate stm32f4xx_hal as hal;
+use hal::prelude::*;
+
+#[allow(dead_code)]
+#[path = "shared/clock.rs"]
+mod clock;
+
+/// System clock, also the CYCCNT rate
+const SYSCLK_HZ: u32 = 16_000_000;
+/// Well past the 268 s it takes CYCCNT to wrap at 16 MHz
+const REPORT_EVERY: Duration = Duration::from_secs(300);
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[rtic::app(device = hal::stm32, peripherals = true, monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        last: Instant,
+        next_report: Instant,
+    }
+
+    #[init(spawn = [tick])]
+    fn init(mut cx: init::Context) -> init::LateResources {
+        cx.core.DCB.enable_trace();
+        cx.core.DWT.enable_cycle_counter();
+
+        let rcc = cx.device.RCC.constrain();
+        let clocks = rcc.cfgr.sysclk(SYSCLK_HZ.hz()).freeze();
+
+        // The 64 bit clock needs no resource, tasks just call `clock::now()`
+        clock::init(cx.device.TIM2, cx.device.TIM5, clocks);
+        let now = clock::now();
+
+        cx.spawn.tick().unwrap();
+
+        // Initialization of late resources
+        init::LateResources {
+            last: now,
+            next_report: now + REPORT_EVERY,
+        }
+    }
+
+    // Scheduled by CYCCNT every second, timed by the 64 bit clock
+    #[task(schedule = [tick], resources = [last, next_report])]
+    fn tick(cx: tick::Context) {
+        let now = clock::now();
+        let interval = now - *cx.resources.last;
+        *cx.resources.last = now;
+
+        if now >= *cx.resources.next_report {
+            *cx.resources.next_report += REPORT_EVERY;
+            let uptime = now - Instant::from_micros(0);
+            iprintln!(
+                itm(),
+                "up {} s, last interval {} us",
+                uptime.as_secs(),
+                interval.as_micros()
+            );
+        }
+
+        cx.schedule.tick(cx.scheduled + SYSCLK_HZ.cycles()).unwrap();
+    }
+
+    // This is required for the software tasks
+    // This can be any interrupt not used by hardware
+    extern "C" {
+        fn USART1();
+    }
+};
@@ -0,0 +1,161 @@
+//! Dual and triple ADC modes through the common ADC registers.
+//! Shared by `adc_dual_1.rs` and `adc_triple_1.rs`.
+
+use stm32f4xx_hal::stm32::{ADC1, ADC2, ADC3, ADC_COMMON, DMA2, RCC};
+
+/// Multi ADC modes of ADC_CCR.MULTI
+#[derive(Debug, Clone, Copy)]
+pub enum MultiMode {
+    /// ADC1 and ADC2 convert at the same time
+    DualSimultaneous,
+    /// ADC1 and ADC2 convert the same channel, delayed by `delay` (5..=20) ADC clock cycles
+    DualInterleaved { delay: u8 },
+    /// ADC1, ADC2 and ADC3 convert at the same time
+    TripleSimultaneous,
+    /// ADC1, ADC2 and ADC3 convert the same channel, delayed by `delay` (5..=20) ADC clock cycles
+    TripleInterleaved { delay: u8 },
+}
+
+impl MultiMode {
+    /// ADCs taking part, the results of one conversion in the buffer
+    pub fn adcs(self) -> usize {
+        match self {
+            MultiMode::DualSimultaneous | MultiMode::DualInterleaved { .. } => 2,
+            MultiMode::TripleSimultaneous | MultiMode::TripleInterleaved { .. } => 3,
+        }
+    }
+
+    fn multi(self) -> u8 {
+        match self {
+            MultiMode::DualSimultaneous => 0b00110,
+            MultiMode::DualInterleaved { .. } => 0b00111,
+            MultiMode::TripleSimultaneous => 0b10110,
+            MultiMode::TripleInterleaved { .. } => 0b10111,
+        }
+    }
+
+    fn delay(self) -> u8 {
+        match self {
+            MultiMode::DualInterleaved { delay } | MultiMode::TripleInterleaved { delay } => {
+                delay.max(5).min(20) - 5
+            }
+            _ => 0,
+        }
+    }
+
+    /// DMA mode 2 packs two results into one word, mode 1 moves one half-word per ADC
+    fn dma(self) -> u8 {
+        match self.adcs() {
+            2 => 0b10,
+            _ => 0b01,
+        }
+    }
+}
+
+/// Common ADC registers configured for dual or triple mode.
+/// The ADCs themselves are set up with `stm32f4xx_hal::adc::Adc` before this.
+pub struct MultiAdc {
+    common: ADC_COMMON,
+    mode: MultiMode,
+    /// Written by DMA2 Stream0 from ADC_CDR
+    buffer: &'static mut [u16],
+}
+
+impl MultiAdc {
+    pub fn new(common: ADC_COMMON, mode: MultiMode, buffer: &'static mut [u16]) -> Self {
+        let mut multi = MultiAdc {
+            common,
+            mode,
+            buffer,
+        };
+        multi.set_mode(mode);
+        multi
+    }
+
+    /// Switches mode. Disable the ADCs and stop the DMA first.
+    pub fn set_mode(&mut self, mode: MultiMode) {
+        self.common.ccr.modify(|_, w| unsafe {
+            w.multi()
+                .bits(mode.multi())
+                .delay()
+                .bits(mode.delay())
+                .dma()
+                .bits(mode.dma())
+                // Keep issuing DMA requests for continuous conversions
+                .dds()
+                .set_bit()
+        });
+        self.mode = mode;
+    }
+
+    /// Streams results into the buffer in the order ADC1, ADC2(, ADC3),
+    /// one group per conversion
+    pub fn start_dma(&self, dma: &DMA2) {
+        let adcs = self.mode.adcs();
+        let buffer = &self.buffer;
+        assert!(buffer.len() % adcs == 0, "Buffer must hold whole groups");
+        // Dual modes move ADC2:ADC1 as one word, which lands as two half-words
+        let (len, size) = match adcs {
+            2 => (buffer.len() / 2, 0b10),
+            _ => (buffer.len(), 0b01),
+        };
+        assert!(len <= 0xFFFF, "Buffer too long for one transfer");
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+
+        self.stop_dma(dma);
+        // Overruns from the last run would block the DMA requests
+        unsafe {
+            (*ADC1::ptr()).sr.modify(|_, w| w.ovr().clear_bit());
+            (*ADC2::ptr()).sr.modify(|_, w| w.ovr().clear_bit());
+            (*ADC3::ptr()).sr.modify(|_, w| w.ovr().clear_bit());
+        }
+
+        let stream = &dma.st[0];
+        stream
+            .par
+            .write(|w| unsafe { w.bits(&self.common.cdr as *const _ as u32) });
+        stream
+            .m0ar
+            .write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+        stream.ndtr.write(|w| unsafe { w.bits(len as u32) });
+        stream.cr.write(|w| unsafe {
+            w.chsel()
+                .bits(0)
+                .msize()
+                .bits(size)
+                .psize()
+                .bits(size)
+                .minc()
+                .set_bit()
+                .circ()
+                .set_bit()
+                .dir()
+                .bits(0b00)
+                .pl()
+                .bits(0b10)
+        });
+        stream.cr.modify(|_, w| w.en().set_bit());
+
+        // Multi mode DMA is driven by ADC_CCR, not by ADC1's own DMA bit
+        let adc1 = unsafe { &(*ADC1::ptr()) };
+        adc1.cr2.modify(|_, w| w.dma().clear_bit());
+    }
+
+    /// Stops DMA2 Stream0 and clears its flags
+    pub fn stop_dma(&self, dma: &DMA2) {
+        let stream = &dma.st[0];
+        stream.cr.modify(|_, w| w.en().clear_bit());
+        while stream.cr.read().en().bit_is_set() {}
+        // FEIF0, DMEIF0, TEIF0, HTIF0, TCIF0
+        dma.lifcr.write(|w| unsafe { w.bits(0x3D) });
+    }
+
+    /// Copies the latest results into `samples`, the DMA keeps writing meanwhile
+    pub fn read(&self, samples: &mut [u16]) {
+        for (sample, latest) in samples.iter_mut().zip(self.buffer.iter()) {
+            *sample = unsafe { core::ptr::read_volatile(latest) };
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Binary capture format. All fields are little endian.
+//!
+//! | bytes | field                                  |
+//! |-------|----------------------------------------|
+//! | 2     | sync, `0xA5 0x5A`                      |
+//! | 1     | sequence number, wraps                 |
+//! | 1     | sample count `n`                       |
+//! | 4     | timestamp of the first sample in µs    |
+//! | 2     | sample period in µs                    |
+//! | 2 × n | samples, `u16`                         |
+//! | 1     | XOR of every byte after the sync       |
+//!
+//! The timestamp is not read from a clock. It is the number of samples since the
+//! start of the capture times the nominal period, so it follows the requested
+//! sample rate, not the one the timer actually achieves. Dropped frames still
+//! advance it and show up as sequence gaps.
+//!
+//! Shared by `adc_capture_1.rs` and `tools/capture-decode`, which turns a stream
+//! of these frames into CSV.
+
+pub const SYNC: [u8; 2] = [0xA5, 0x5A];
+/// Samples per frame
+pub const SAMPLES: usize = 64;
+/// Bytes before the samples
+pub const HEADER: usize = 10;
+/// Size of a full frame in bytes
+pub const FRAME_MAX: usize = HEADER + SAMPLES * 2 + 1;
+
+/// A block of samples being collected
+pub struct Block {
+    samples: [u16; SAMPLES],
+    len: usize,
+    sequence: u8,
+    period_us: u16,
+    timestamp_us: u32,
+}
+
+impl Block {
+    pub fn new(period_us: u16) -> Self {
+        Block {
+            samples: [0; SAMPLES],
+            len: 0,
+            sequence: 0,
+            period_us,
+            timestamp_us: 0,
+        }
+    }
+
+    /// Adds a sample. Returns true once the block is full.
+    pub fn push(&mut self, sample: u16) -> bool {
+        if self.len < SAMPLES {
+            self.samples[self.len] = sample;
+            self.len += 1;
+        }
+        self.len == SAMPLES
+    }
+
+    /// Writes the block as a frame into `buf` and returns the frame length
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        let ts = self.timestamp_us.to_le_bytes();
+        let period = self.period_us.to_le_bytes();
+        buf[..2].copy_from_slice(&SYNC);
+        buf[2] = self.sequence;
+        buf[3] = self.len as u8;
+        buf[4..8].copy_from_slice(&ts);
+        buf[8..10].copy_from_slice(&period);
+        for (i, sample) in self.samples[..self.len].iter().enumerate() {
+            buf[HEADER + i * 2..HEADER + i * 2 + 2].copy_from_slice(&sample.to_le_bytes());
+        }
+        let end = HEADER + self.len * 2;
+        buf[end] = buf[2..end].iter().fold(0, |acc, &b| acc ^ b);
+        end + 1
+    }
+
+    /// Starts the next block, continuing the timeline
+    pub fn next(&mut self) {
+        self.timestamp_us = self
+            .timestamp_us
+            .wrapping_add(self.len as u32 * self.period_us as u32);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.len = 0;
+    }
+}
@@ -0,0 +1,241 @@
+//! 64 bit microsecond clock from TIM2 and TIM5 chained in hardware.
+//! Shared by `timer_clock_1.rs` and `rtfm_4.rs`.
+//!
+//! TIM2 counts microseconds, TIM5 counts TIM2 overflows through TRGO, so no
+//! interrupt is involved and `now` can be called from anywhere, RTIC tasks included.
+
+use core::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+use stm32f4xx_hal::rcc::Clocks;
+use stm32f4xx_hal::stm32::{RCC, TIM2, TIM5};
+
+/// Point in time, microseconds since `init`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    micros: u64,
+}
+
+/// Span of time in microseconds.
+///
+/// Subtraction saturates at zero throughout, for instants and durations alike,
+/// the `checked_` methods tell when that happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Duration {
+    micros: u64,
+}
+
+/// Starts the clock. TIM2 and TIM5 are used up for good.
+pub fn init(tim2: TIM2, tim5: TIM5, clocks: Clocks) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.apb1enr
+        .modify(|_, w| w.tim2en().set_bit().tim5en().set_bit());
+
+    let clock = if clocks.ppre1() == 1 {
+        clocks.pclk1().0
+    } else {
+        clocks.pclk1().0 * 2
+    };
+    // `now` relies on TIM2 holding 0 for longer than TIM5 takes to see the overflow
+    assert!(clock % 1_000_000 == 0 && clock >= 8_000_000);
+
+    // TIM2: 1 MHz, full 32 bit range. Load PSC before the trigger output is on,
+    // the UG event would count as an overflow otherwise.
+    tim2.cr1.modify(|_, w| w.cen().clear_bit());
+    tim2.psc
+        .write(|w| w.psc().bits((clock / 1_000_000 - 1) as u16));
+    tim2.arr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+    tim2.egr.write(|w| w.ug().set_bit());
+    tim2.sr.modify(|_, w| w.uif().clear_bit());
+    tim2.cnt.reset();
+    // TRGO on update
+    tim2.cr2.modify(|_, w| unsafe { w.mms().bits(0b010) });
+
+    // TIM5: external clock mode 1 from ITR0 (TIM2 TRGO)
+    tim5.arr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+    tim5.cnt.reset();
+    tim5.smcr
+        .write(|w| unsafe { w.ts().bits(0b000).sms().bits(0b111) });
+    tim5.cr1.modify(|_, w| w.cen().set_bit());
+    tim2.cr1.modify(|_, w| w.cen().set_bit());
+}
+
+/// Current time. Zero before `init`.
+pub fn now() -> Instant {
+    let (tim2, tim5) = unsafe { (&(*TIM2::ptr()), &(*TIM5::ptr())) };
+    if tim2.cr1.read().cen().bit_is_clear() {
+        return Instant::from_micros(0);
+    }
+    loop {
+        let high = tim5.cnt.read().bits();
+        let low = tim2.cnt.read().bits();
+        // TRGO is resynchronized into TIM5, so it counts a few timer clock cycles after
+        // TIM2 wraps. TIM2 reads 0 for a whole microsecond then, wait until it moves on
+        // rather than pair it with a stale TIM5.
+        if low == 0 {
+            continue;
+        }
+        // Retry if TIM2 overflowed in between
+        if tim5.cnt.read().bits() == high {
+            return Instant {
+                micros: (high as u64) << 32 | low as u64,
+            };
+        }
+    }
+}
+
+impl Instant {
+    pub const fn from_micros(micros: u64) -> Self {
+        Instant { micros }
+    }
+
+    pub fn as_micros(self) -> u64 {
+        self.micros
+    }
+
+    /// Time since `earlier`, `None` if it is later than `self`
+    pub fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
+        self.micros
+            .checked_sub(earlier.micros)
+            .map(Duration::from_micros)
+    }
+
+    /// Time since `earlier`, zero if it is later than `self`
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Time since `self`
+    pub fn elapsed(self) -> Duration {
+        now().duration_since(self)
+    }
+
+    /// `None` if `duration` reaches back past `init`
+    pub fn checked_sub(self, duration: Duration) -> Option<Instant> {
+        self.micros
+            .checked_sub(duration.micros)
+            .map(Instant::from_micros)
+    }
+}
+
+impl Duration {
+    pub const fn from_micros(micros: u64) -> Self {
+        Duration { micros }
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Duration {
+            micros: millis * 1_000,
+        }
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Duration {
+            micros: secs * 1_000_000,
+        }
+    }
+
+    pub fn as_micros(self) -> u64 {
+        self.micros
+    }
+
+    pub fn as_millis(self) -> u64 {
+        self.micros / 1_000
+    }
+
+    pub fn as_secs(self) -> u64 {
+        self.micros / 1_000_000
+    }
+
+    /// Microseconds within the last second
+    pub fn subsec_micros(self) -> u32 {
+        (self.micros % 1_000_000) as u32
+    }
+
+    pub fn checked_sub(self, other: Duration) -> Option<Duration> {
+        self.micros
+            .checked_sub(other.micros)
+            .map(Duration::from_micros)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        Instant::from_micros(self.micros + other.micros)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+/// Saturates at zero like `duration_since`
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, other: Duration) -> Instant {
+        Instant::from_micros(self.micros.saturating_sub(other.micros))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+/// Saturates at zero like `duration_since`
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, other: Instant) -> Duration {
+        self.duration_since(other)
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration::from_micros(self.micros + other.micros)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+/// Saturates at zero like `duration_since`
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, other: Duration) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(other.micros))
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Mul<u32> for Duration {
+    type Output = Duration;
+
+    fn mul(self, factor: u32) -> Duration {
+        Duration::from_micros(self.micros * factor as u64)
+    }
+}
+
+impl Div<u32> for Duration {
+    type Output = Duration;
+
+    fn div(self, divisor: u32) -> Duration {
+        Duration::from_micros(self.micros / divisor as u64)
+    }
+}
@@ -0,0 +1,235 @@
+//! ADC-to-PWM transfer curves in integer math.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// Full scale of a 12 bit sample
+pub const ADC_MAX: u16 = 0x0FFF;
+/// Full scale of the curve output, 1.0 in Q16
+pub const OUT_MAX: u16 = 0xFFFF;
+
+/// x^2.2, for perceived LED brightness
+pub const GAMMA_2_2: [(u16, u16); 17] = [
+    (0, 0),
+    (256, 147),
+    (512, 676),
+    (768, 1649),
+    (1024, 3106),
+    (1280, 5074),
+    (1536, 7578),
+    (1792, 10638),
+    (2048, 14271),
+    (2304, 18492),
+    (2560, 23315),
+    (2816, 28755),
+    (3072, 34821),
+    (3328, 41526),
+    (3584, 48879),
+    (3840, 56891),
+    (4095, 65535),
+];
+
+/// (e^5x - 1) / (e^5 - 1), for log taper controls such as audio volume or motor speed
+pub const EXPONENTIAL: [(u16, u16); 17] = [
+    (0, 0),
+    (256, 163),
+    (512, 386),
+    (768, 691),
+    (1024, 1108),
+    (1280, 1677),
+    (1536, 2456),
+    (1792, 3520),
+    (2048, 4975),
+    (2304, 6963),
+    (2560, 9681),
+    (2816, 13397),
+    (3072, 18476),
+    (3328, 25419),
+    (3584, 34910),
+    (3840, 47882),
+    (4095, 65535),
+];
+
+/// Transfer curve from a 12 bit sample to a Q16 fraction of full scale
+#[derive(Debug, Clone, Copy)]
+pub enum Curve {
+    Linear,
+    /// x^2, a cheap approximation of gamma 2.2
+    Square,
+    /// Piecewise-linear lookup table of `(input, output)` points.
+    /// Inputs must be ascending. Inputs outside the table are clamped.
+    Table(&'static [(u16, u16)]),
+}
+
+impl Curve {
+    /// Evaluates the curve. Returns 0..=`OUT_MAX`.
+    pub fn eval(self, x: u16) -> u16 {
+        let x = x.min(ADC_MAX) as u32;
+        match self {
+            Curve::Linear => (x * OUT_MAX as u32 / ADC_MAX as u32) as u16,
+            Curve::Square => {
+                let x = x as u64;
+                (x * x * OUT_MAX as u64 / (ADC_MAX as u64 * ADC_MAX as u64)) as u16
+            }
+            Curve::Table(table) => interpolate(table, x),
+        }
+    }
+}
+
+fn interpolate(table: &[(u16, u16)], x: u32) -> u16 {
+    let (first, last) = match (table.first(), table.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return 0,
+    };
+    if x <= first.0 as u32 {
+        return first.1;
+    }
+    if x >= last.0 as u32 {
+        return last.1;
+    }
+    // Find the segment containing x
+    let i = table.iter().position(|&(xi, _)| xi as u32 > x).unwrap_or(1);
+    let (x0, y0) = (table[i - 1].0 as i32, table[i - 1].1 as i32);
+    let (x1, y1) = (table[i].0 as i32, table[i].1 as i32);
+    (y0 + (y1 - y0) * (x as i32 - x0) / (x1 - x0)) as u16
+}
+
+/// Maps ADC samples to PWM duty through a `Curve`, with optional dead-band and hysteresis.
+/// Integer math only, cheap enough for interrupt handlers.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapper {
+    curve: Curve,
+    max_duty: u16,
+    dead_band: u16,
+    hysteresis: u16,
+    last: Option<(u16, u16)>,
+}
+
+impl Mapper {
+    pub fn new(curve: Curve, max_duty: u16) -> Self {
+        Mapper {
+            curve,
+            max_duty,
+            dead_band: 0,
+            hysteresis: 0,
+            last: None,
+        }
+    }
+
+    /// Samples at or below `threshold` map to 0. The rest of the range is stretched to
+    /// full scale, so the output stays continuous.
+    pub fn dead_band(self, threshold: u16) -> Self {
+        Mapper {
+            dead_band: threshold.min(ADC_MAX - 1),
+            ..self
+        }
+    }
+
+    /// The output only changes once the sample has moved more than `counts` away from
+    /// the sample that produced the current output.
+    pub fn hysteresis(self, counts: u16) -> Self {
+        Mapper {
+            hysteresis: counts,
+            ..self
+        }
+    }
+
+    /// Returns the duty cycle for `sample`
+    pub fn map(&mut self, sample: u16) -> u16 {
+        if let Some((input, duty)) = self.last {
+            if sample.abs_diff(input) <= self.hysteresis {
+                return duty;
+            }
+        }
+        let duty = self.duty(sample);
+        self.last = Some((sample, duty));
+        duty
+    }
+
+    fn duty(&self, sample: u16) -> u16 {
+        let sample = sample.min(ADC_MAX);
+        if sample <= self.dead_band {
+            return 0;
+        }
+        let x =
+            (sample - self.dead_band) as u32 * ADC_MAX as u32 / (ADC_MAX - self.dead_band) as u32;
+        let y = self.curve.eval(x as u16) as u32;
+        (y * self.max_duty as u32 / OUT_MAX as u32) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_spans_full_scale() {
+        assert_eq!(Curve::Linear.eval(0), 0);
+        assert_eq!(Curve::Linear.eval(ADC_MAX), OUT_MAX);
+        // Out of range samples are clamped
+        assert_eq!(Curve::Linear.eval(0xFFFF), OUT_MAX);
+        assert_eq!(Curve::Square.eval(ADC_MAX), OUT_MAX);
+        assert_eq!(Curve::Square.eval(ADC_MAX / 2), 16_375);
+    }
+
+    #[test]
+    fn table_hits_its_points() {
+        for table in [&GAMMA_2_2, &EXPONENTIAL].iter() {
+            for &(x, y) in table.iter() {
+                assert_eq!(Curve::Table(*table).eval(x), y);
+            }
+        }
+    }
+
+    #[test]
+    fn table_interpolates_between_points() {
+        // Halfway between (256, 147) and (512, 676)
+        assert_eq!(Curve::Table(&GAMMA_2_2).eval(384), 411);
+
+        const SHORT: [(u16, u16); 3] = [(100, 1_000), (200, 3_000), (300, 2_000)];
+        let curve = Curve::Table(&SHORT);
+        assert_eq!(curve.eval(150), 2_000);
+        // Falling segments work too
+        assert_eq!(curve.eval(250), 2_500);
+        // Clamped outside the table
+        assert_eq!(curve.eval(0), 1_000);
+        assert_eq!(curve.eval(4_000), 2_000);
+        assert_eq!(Curve::Table(&[]).eval(100), 0);
+    }
+
+    #[test]
+    fn tables_are_monotonic() {
+        for table in [&GAMMA_2_2, &EXPONENTIAL].iter() {
+            let curve = Curve::Table(*table);
+            let mut last = 0;
+            for x in 0..=ADC_MAX {
+                let y = curve.eval(x);
+                assert!(y >= last, "x {}: {} < {}", x, y, last);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn dead_band_maps_to_zero_and_stays_continuous() {
+        let mut mapper = Mapper::new(Curve::Linear, 1_000).dead_band(64);
+        assert_eq!(mapper.map(0), 0);
+        assert_eq!(mapper.map(64), 0);
+        // Just above the band the output starts from 0, not from 64 / 4095
+        assert!(mapper.map(65) <= 1);
+        assert_eq!(mapper.map(ADC_MAX), 1_000);
+        // The remaining range is stretched to full scale
+        assert_eq!(mapper.map(64 + (ADC_MAX - 64) / 2), 499);
+    }
+
+    #[test]
+    fn hysteresis_holds_small_changes() {
+        let duty = |x| Curve::Linear.eval(x);
+        let mut mapper = Mapper::new(Curve::Linear, OUT_MAX).hysteresis(8);
+        assert_eq!(mapper.map(1_000), duty(1_000));
+        assert_eq!(mapper.map(1_008), duty(1_000));
+        assert_eq!(mapper.map(992), duty(1_000));
+        // Moving further updates the reference sample
+        assert_eq!(mapper.map(1_009), duty(1_009));
+        assert_eq!(mapper.map(1_001), duty(1_009));
+        assert_eq!(mapper.map(1_000), duty(1_000));
+    }
+}
@@ -0,0 +1,98 @@
+//! Dead-time encoding of the advanced timers' BDTR.DTG field.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// Longest dead-time DTG can hold, in t_DTS ticks
+pub const MAX_TICKS: u32 = (32 + 31) * 16;
+
+/// Encodes a dead-time of at least `dead_time_ns` into BDTR.DTG, for t_DTS = 1 / `clock` Hz
+/// (CKD = 0). Rounds up to the next step DTG can represent, `None` if it is too long.
+pub fn dead_time_bits(dead_time_ns: u32, clock: u32) -> Option<u8> {
+    let ticks = (dead_time_ns as u64 * clock as u64).div_ceil(1_000_000_000);
+    match ticks {
+        // DT = DTG * t_DTS
+        0..=127 => Some(ticks as u8),
+        // DT = (64 + DTG[5:0]) * 2 * t_DTS
+        128..=254 => Some(0b1000_0000 | (ticks.div_ceil(2) - 64) as u8),
+        // DT = (32 + DTG[4:0]) * 8 * t_DTS
+        255..=504 => Some(0b1100_0000 | (ticks.div_ceil(8) - 32) as u8),
+        // DT = (32 + DTG[4:0]) * 16 * t_DTS
+        505..=1008 => Some(0b1110_0000 | (ticks.div_ceil(16) - 32) as u8),
+        _ => None,
+    }
+}
+
+/// Dead-time in t_DTS ticks that `dtg` encodes
+pub fn dead_time_ticks(dtg: u8) -> u32 {
+    let dtg = dtg as u32;
+    match dtg >> 5 {
+        0b000..=0b011 => dtg,
+        0b100 | 0b101 => (64 + (dtg & 0x3F)) * 2,
+        0b110 => (32 + (dtg & 0x1F)) * 8,
+        _ => (32 + (dtg & 0x1F)) * 16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 ns per tick keeps the arithmetic obvious
+    const GHZ: u32 = 1_000_000_000;
+
+    #[test]
+    fn every_step_round_trips() {
+        for dtg in 0..=255u8 {
+            let ticks = dead_time_ticks(dtg);
+            assert_eq!(dead_time_bits(ticks, GHZ), Some(dtg), "{} ticks", ticks);
+        }
+    }
+
+    #[test]
+    fn rounds_up_to_the_next_step() {
+        for ns in 0..=MAX_TICKS {
+            let ticks = dead_time_ticks(dead_time_bits(ns, GHZ).unwrap());
+            assert!(ticks >= ns, "{} ns became {} ticks", ns, ticks);
+            // Never more than one step long, the steps are 1, 2, 8 and 16 ticks
+            let step = match ns {
+                0..=127 => 1,
+                128..=254 => 2,
+                255..=504 => 8,
+                _ => 16,
+            };
+            assert!(ticks - ns < step, "{} ns became {} ticks", ns, ticks);
+        }
+    }
+
+    #[test]
+    fn boundaries() {
+        assert_eq!(dead_time_bits(127, GHZ), Some(127));
+        assert_eq!(dead_time_bits(128, GHZ), Some(0b1000_0000));
+        assert_eq!(dead_time_bits(254, GHZ), Some(0b1011_1111));
+        assert_eq!(dead_time_bits(255, GHZ), Some(0b1100_0000));
+        assert_eq!(dead_time_bits(504, GHZ), Some(0b1101_1111));
+        assert_eq!(dead_time_bits(505, GHZ), Some(0b1110_0000));
+        assert_eq!(dead_time_bits(MAX_TICKS, GHZ), Some(0xFF));
+    }
+
+    #[test]
+    fn too_long_is_rejected() {
+        assert_eq!(dead_time_bits(MAX_TICKS + 1, GHZ), None);
+        // 6 µs at 168 MHz is 1008 ticks, the longest there is
+        assert_eq!(dead_time_bits(6_000, 168_000_000), Some(0xFF));
+        assert_eq!(dead_time_bits(6_010, 168_000_000), None);
+        assert_eq!(dead_time_bits(u32::MAX, 168_000_000), None);
+    }
+
+    #[test]
+    fn converts_from_nanoseconds() {
+        // 500 ns at 84 MHz is 42 ticks
+        assert_eq!(dead_time_bits(500, 84_000_000), Some(42));
+        // 1 µs at 168 MHz is 168 ticks, 2 tick steps from 128
+        assert_eq!(
+            dead_time_ticks(dead_time_bits(1_000, 168_000_000).unwrap()),
+            168
+        );
+        // Fractions of a tick round up
+        assert_eq!(dead_time_bits(1, 84_000_000), Some(1));
+    }
+}
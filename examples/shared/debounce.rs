@@ -0,0 +1,126 @@
+//! Debounced buttons on EXTI lines.
+//! Shared by `gpio_debounce_1.rs` and `gpio_gesture_1.rs`.
+
+use stm32f4xx_hal::gpio::{Edge, ExtiPin};
+use stm32f4xx_hal::hal::digital::v2::InputPin;
+use stm32f4xx_hal::stm32::{EXTI, SYSCFG};
+
+/// Level of the pin while the button is pressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Active {
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Pressed,
+    Released,
+}
+
+/// Debounced button on an EXTI line.
+///
+/// The first edge masks the line and starts the delay. Edges during the delay
+/// are ignored. When it runs out the level is read, compared with the last
+/// stable state and the line is unmasked again.
+pub struct Debouncer<P> {
+    pin: P,
+    /// Bit of the pin's EXTI line
+    mask: u32,
+    active: Active,
+    /// Delay in ticks
+    delay: u16,
+    /// Ticks left, 0 when idle
+    countdown: u16,
+    pressed: bool,
+}
+
+impl<P> Debouncer<P>
+where
+    P: ExtiPin + InputPin,
+{
+    /// Sets up the pin to interrupt on both edges
+    pub fn new(
+        mut pin: P,
+        active: Active,
+        delay: u16,
+        syscfg: &mut SYSCFG,
+        exti: &mut EXTI,
+    ) -> Self {
+        pin.make_interrupt_source(syscfg);
+        pin.trigger_on_edge(exti, Edge::RISING_FALLING);
+        pin.clear_interrupt_pending_bit();
+        pin.disable_interrupt(exti);
+        let masked = exti.imr.read().bits();
+        pin.enable_interrupt(exti);
+        // The one bit `enable_interrupt` sets is the pin's line
+        let mask = exti.imr.read().bits() & !masked;
+        let mut debouncer = Debouncer {
+            pin,
+            mask,
+            active,
+            delay: delay.max(1),
+            countdown: 0,
+            pressed: false,
+        };
+        debouncer.pressed = debouncer.is_active();
+        debouncer
+    }
+
+    /// Call from the EXTI interrupt. Does nothing unless this line is pending and unmasked.
+    /// Edges during the delay set the pending bit too, they must not restart it.
+    pub fn on_edge(&mut self, exti: &mut EXTI) {
+        let triggered = exti.imr.read().bits() & exti.pr.read().bits();
+        if triggered & self.mask == 0 {
+            return;
+        }
+        self.pin.disable_interrupt(exti);
+        self.pin.clear_interrupt_pending_bit();
+        self.countdown = self.delay;
+    }
+
+    /// Call on every timer tick. Returns an event when a new level is confirmed.
+    pub fn on_tick(&mut self, exti: &mut EXTI) -> Option<Event> {
+        if self.countdown == 0 {
+            return None;
+        }
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return None;
+        }
+
+        let pressed = self.is_active();
+        // Edges while masked still set the pending bit
+        self.pin.clear_interrupt_pending_bit();
+        self.pin.enable_interrupt(exti);
+        // A change between the read and the unmask has no edge left to report it
+        if self.is_active() != pressed {
+            self.pin.disable_interrupt(exti);
+            self.countdown = self.delay;
+        }
+
+        if pressed == self.pressed {
+            return None;
+        }
+        self.pressed = pressed;
+        Some(if pressed {
+            Event::Pressed
+        } else {
+            Event::Released
+        })
+    }
+
+    /// Debounced state
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    pub fn release(self) -> P {
+        self.pin
+    }
+
+    fn is_active(&self) -> bool {
+        let high = self.pin.is_high().ok().unwrap_or(false);
+        high == (self.active == Active::High)
+    }
+}
@@ -0,0 +1,441 @@
+//! Allocation-free filters for sample streams, in fixed-point and f32.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// 9 tap Hamming windowed low-pass, fc = 0.1 fs, Q15
+pub const FIR_LOWPASS: [i16; 9] = [166, 962, 3629, 7187, 8879, 7187, 3629, 962, 166];
+
+/// `FIR_LOWPASS` in f32
+pub const FIR_LOWPASS_F32: [f32; 9] = [
+    0.005_065_918,
+    0.029_357_91,
+    0.110_748_29,
+    0.219_329_83,
+    0.270_965_58,
+    0.219_329_83,
+    0.110_748_29,
+    0.029_357_91,
+    0.005_065_918,
+];
+
+/// 2nd order Butterworth low-pass, fc = 50 Hz at fs = 1 kHz
+pub const BIQUAD_LOWPASS: BiquadF32 = BiquadF32::new(
+    [0.020_083_366, 0.040_166_73, 0.020_083_366],
+    [-1.561_018_1, 0.641_351_5],
+);
+
+/// The same low-pass in Q28 for the fixed-point path
+pub const BIQUAD_LOWPASS_Q28: Biquad<i16> = Biquad::new(
+    [5_391_087, 10_782_175, 5_391_087],
+    [-419_032_599, 172_161_493],
+);
+
+/// A filter that takes one sample and returns one sample
+pub trait Filter {
+    type Sample;
+    fn process(&mut self, input: Self::Sample) -> Self::Sample;
+}
+
+/// Fixed-point sample types
+pub trait Fixed: Copy + Default + Ord {
+    fn to_i32(self) -> i32;
+    /// Saturates to the range of `Self`
+    fn from_i32(x: i32) -> Self;
+}
+
+impl Fixed for i16 {
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+    fn from_i32(x: i32) -> Self {
+        x.max(i16::MIN as i32).min(i16::MAX as i32) as i16
+    }
+}
+
+impl Fixed for u16 {
+    fn to_i32(self) -> i32 {
+        self as i32
+    }
+    fn from_i32(x: i32) -> Self {
+        x.max(0).min(u16::MAX as i32) as u16
+    }
+}
+
+/// Moving average over the length of `buffer`
+pub struct MovingAverage<'a, T> {
+    buffer: &'a mut [T],
+    index: usize,
+    sum: i32,
+}
+
+impl<'a, T: Fixed> MovingAverage<'a, T> {
+    pub fn new(buffer: &'a mut [T]) -> Self {
+        assert!(!buffer.is_empty());
+        let sum = buffer.iter().map(|x| x.to_i32()).sum();
+        MovingAverage {
+            buffer,
+            index: 0,
+            sum,
+        }
+    }
+}
+
+impl<'a, T: Fixed> Filter for MovingAverage<'a, T> {
+    type Sample = T;
+    fn process(&mut self, input: T) -> T {
+        // Running sum, O(1) per sample
+        self.sum += input.to_i32() - self.buffer[self.index].to_i32();
+        self.buffer[self.index] = input;
+        self.index = (self.index + 1) % self.buffer.len();
+        T::from_i32(self.sum / self.buffer.len() as i32)
+    }
+}
+
+/// Median over the length of `window`. Use an odd length.
+pub struct Median<'a, T> {
+    window: &'a mut [T],
+    sorted: &'a mut [T],
+    index: usize,
+}
+
+impl<'a, T: Fixed> Median<'a, T> {
+    /// `window` and `sorted` must have the same length
+    pub fn new(window: &'a mut [T], sorted: &'a mut [T]) -> Self {
+        assert!(!window.is_empty() && window.len() == sorted.len());
+        sorted.copy_from_slice(window);
+        sorted.sort_unstable();
+        Median {
+            window,
+            sorted,
+            index: 0,
+        }
+    }
+}
+
+impl<'a, T: Fixed> Filter for Median<'a, T> {
+    type Sample = T;
+    fn process(&mut self, input: T) -> T {
+        let oldest = self.window[self.index];
+        self.window[self.index] = input;
+        self.index = (self.index + 1) % self.window.len();
+        // Replace the oldest sample in the sorted copy and move it into place
+        let mut i = self.sorted.binary_search(&oldest).unwrap_or_else(|i| i);
+        self.sorted[i] = input;
+        while i > 0 && self.sorted[i - 1] > self.sorted[i] {
+            self.sorted.swap(i - 1, i);
+            i -= 1;
+        }
+        while i + 1 < self.sorted.len() && self.sorted[i + 1] < self.sorted[i] {
+            self.sorted.swap(i, i + 1);
+            i += 1;
+        }
+        self.sorted[self.sorted.len() / 2]
+    }
+}
+
+/// FIR filter with Q15 coefficients. `history` must be as long as `coefficients`.
+pub struct Fir<'a, T> {
+    coefficients: &'a [i16],
+    history: &'a mut [T],
+    index: usize,
+}
+
+impl<'a, T: Fixed> Fir<'a, T> {
+    pub fn new(coefficients: &'a [i16], history: &'a mut [T]) -> Self {
+        assert!(!coefficients.is_empty() && coefficients.len() == history.len());
+        Fir {
+            coefficients,
+            history,
+            index: 0,
+        }
+    }
+}
+
+impl<'a, T: Fixed> Filter for Fir<'a, T> {
+    type Sample = T;
+    fn process(&mut self, input: T) -> T {
+        let len = self.history.len();
+        self.history[self.index] = input;
+        // history[index] is the newest sample, walk backwards in time
+        let acc = self
+            .coefficients
+            .iter()
+            .enumerate()
+            .fold(0i64, |acc, (k, &c)| {
+                let x = self.history[(self.index + len - k) % len].to_i32();
+                acc + c as i64 * x as i64
+            });
+        self.index = (self.index + 1) % len;
+        // Round and drop the Q15 fraction
+        T::from_i32(((acc + (1 << 14)) >> 15) as i32)
+    }
+}
+
+/// FIR filter in f32. `history` must be as long as `coefficients`.
+pub struct FirF32<'a> {
+    coefficients: &'a [f32],
+    history: &'a mut [f32],
+    index: usize,
+}
+
+impl<'a> FirF32<'a> {
+    pub fn new(coefficients: &'a [f32], history: &'a mut [f32]) -> Self {
+        assert!(!coefficients.is_empty() && coefficients.len() == history.len());
+        FirF32 {
+            coefficients,
+            history,
+            index: 0,
+        }
+    }
+}
+
+impl<'a> Filter for FirF32<'a> {
+    type Sample = f32;
+    fn process(&mut self, input: f32) -> f32 {
+        let len = self.history.len();
+        self.history[self.index] = input;
+        let y = self
+            .coefficients
+            .iter()
+            .enumerate()
+            .fold(0.0, |acc, (k, &c)| {
+                acc + c * self.history[(self.index + len - k) % len]
+            });
+        self.index = (self.index + 1) % len;
+        y
+    }
+}
+
+/// Biquad section in Q28 fixed-point, Direct Form I.
+/// `b` = [b0, b1, b2] and `a` = [a1, a2], normalized so that a0 = 1.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad<T> {
+    b: [i32; 3],
+    a: [i32; 2],
+    x: [i32; 2],
+    y: [i32; 2],
+    _sample: core::marker::PhantomData<T>,
+}
+
+impl<T> Biquad<T> {
+    pub const fn new(b: [i32; 3], a: [i32; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0; 2],
+            y: [0; 2],
+            _sample: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Fixed> Filter for Biquad<T> {
+    type Sample = T;
+    fn process(&mut self, input: T) -> T {
+        let x0 = input.to_i32();
+        let acc = self.b[0] as i64 * x0 as i64
+            + self.b[1] as i64 * self.x[0] as i64
+            + self.b[2] as i64 * self.x[1] as i64
+            - self.a[0] as i64 * self.y[0] as i64
+            - self.a[1] as i64 * self.y[1] as i64;
+        let y0 = ((acc + (1 << 27)) >> 28) as i32;
+        self.x = [x0, self.x[0]];
+        self.y = [y0, self.y[0]];
+        T::from_i32(y0)
+    }
+}
+
+/// Biquad section in f32, Direct Form II transposed.
+/// `b` = [b0, b1, b2] and `a` = [a1, a2], normalized so that a0 = 1.
+#[derive(Debug, Clone, Copy)]
+pub struct BiquadF32 {
+    b: [f32; 3],
+    a: [f32; 2],
+    z: [f32; 2],
+}
+
+impl BiquadF32 {
+    pub const fn new(b: [f32; 3], a: [f32; 2]) -> Self {
+        BiquadF32 { b, a, z: [0.0; 2] }
+    }
+}
+
+impl Filter for BiquadF32 {
+    type Sample = f32;
+    fn process(&mut self, input: f32) -> f32 {
+        let y = self.b[0] * input + self.z[0];
+        self.z[0] = self.b[1] * input - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * input - self.a[1] * y;
+        y
+    }
+}
+
+/// Biquad sections in series
+pub struct Cascade<'a> {
+    stages: &'a mut [BiquadF32],
+}
+
+impl<'a> Cascade<'a> {
+    pub fn new(stages: &'a mut [BiquadF32]) -> Self {
+        Cascade { stages }
+    }
+}
+
+impl<'a> Filter for Cascade<'a> {
+    type Sample = f32;
+    fn process(&mut self, input: f32) -> f32 {
+        self.stages
+            .iter_mut()
+            .fold(input, |x, stage| stage.process(x))
+    }
+}
+
+/// Fixed-point biquad sections in series
+pub struct CascadeFixed<'a, T> {
+    stages: &'a mut [Biquad<T>],
+}
+
+impl<'a, T: Fixed> CascadeFixed<'a, T> {
+    pub fn new(stages: &'a mut [Biquad<T>]) -> Self {
+        CascadeFixed { stages }
+    }
+}
+
+impl<'a, T: Fixed> Filter for CascadeFixed<'a, T> {
+    type Sample = T;
+    fn process(&mut self, input: T) -> T {
+        self.stages
+            .iter_mut()
+            .fold(input, |x, stage| stage.process(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference outputs computed in double precision, see each test
+    const RAW: [u16; 32] = [
+        2078, 2276, 2484, 2623, 2626, 4095, 2464, 2294, 2038, 1810, 1596, 0, 1450, 1513, 1640,
+        1828, 2062, 2260, 2468, 4095, 2674, 2613, 2448, 2278, 2022, 1794, 1644, 1491, 1434, 1497,
+        1624, 1812,
+    ];
+
+    fn centered() -> Vec<i16> {
+        RAW.iter().map(|&x| x as i16 - 2048).collect()
+    }
+
+    fn run<F: Filter>(filter: &mut F, input: &[F::Sample]) -> Vec<F::Sample>
+    where
+        F::Sample: Copy,
+    {
+        input.iter().map(|&x| filter.process(x)).collect()
+    }
+
+    #[test]
+    fn median_removes_spikes() {
+        // Median of the last 5 samples, the window starts out as zeros
+        const EXPECTED: [u16; 32] = [
+            0, 0, 2078, 2276, 2484, 2623, 2623, 2623, 2464, 2294, 2038, 1810, 1596, 1513, 1513,
+            1513, 1640, 1828, 2062, 2260, 2468, 2613, 2613, 2613, 2448, 2278, 2022, 1794, 1644,
+            1497, 1497, 1497,
+        ];
+        let (mut window, mut sorted) = ([0u16; 5], [0u16; 5]);
+        let mut median = Median::new(&mut window, &mut sorted);
+        assert_eq!(run(&mut median, &RAW), EXPECTED);
+    }
+
+    #[test]
+    fn moving_average() {
+        // floor(sum of the last 8 samples / 8), the window starts out as zeros
+        const EXPECTED: [u16; 32] = [
+            259, 544, 854, 1182, 1510, 2022, 2330, 2617, 2612, 2554, 2443, 2115, 1968, 1645, 1542,
+            1484, 1487, 1543, 1652, 2164, 2317, 2455, 2556, 2612, 2607, 2549, 2446, 2120, 1965,
+            1826, 1723, 1664,
+        ];
+        let mut buffer = [0u16; 8];
+        let mut average = MovingAverage::new(&mut buffer);
+        assert_eq!(run(&mut average, &RAW), EXPECTED);
+    }
+
+    /// Exact convolution with `FIR_LOWPASS` / 32768, rounded half up
+    const FIR_EXPECTED: [i16; 32] = [
+        0, 2, 12, 48, 126, 255, 424, 644, 840, 876, 688, 352, -5, -363, -700, -883, -837, -630,
+        -395, -187, 48, 362, 690, 882, 846, 642, 402, 193, -12, -206, -368, -477,
+    ];
+
+    /// The same convolution unrounded
+    const FIR_EXACT: [f32; 32] = [
+        0.152, 2.036, 12.225, 47.543, 126.231, 255.007, 423.801, 644.498, 840.075, 876.061,
+        688.363, 352.286, -5.073, -363.072, -699.643, -882.982, -837.236, -629.78, -394.888,
+        -187.031, 47.899, 361.523, 690.238, 882.342, 846.243, 641.656, 401.922, 192.86, -12.456,
+        -205.563, -367.771, -476.599,
+    ];
+
+    /// Two `BIQUAD_LOWPASS` sections from the design coefficients
+    const IIR_EXACT: [f32; 32] = [
+        0.012, 0.178, 1.127, 4.417, 12.545, 28.864, 57.81, 103.314, 165.099, 237.03, 309.0,
+        369.115, 404.744, 404.968, 364.912, 287.763, 183.077, 64.039, -54.897, -159.42, -235.686,
+        -272.169, -263.661, -213.235, -130.58, -29.247, 75.762, 170.122, 241.938, 282.988, 289.485,
+        262.345,
+    ];
+
+    #[test]
+    fn fir_q15() {
+        let mut history = [0i16; 9];
+        let mut fir = Fir::new(&FIR_LOWPASS, &mut history);
+        assert_eq!(run(&mut fir, &centered()), FIR_EXPECTED);
+    }
+
+    #[test]
+    fn fir_f32() {
+        let input: Vec<f32> = centered().iter().map(|&x| x as f32).collect();
+        let mut history = [0.0f32; 9];
+        let mut fir = FirF32::new(&FIR_LOWPASS_F32, &mut history);
+        for (y, expected) in run(&mut fir, &input).iter().zip(FIR_EXACT.iter()) {
+            assert!((y - expected).abs() < 0.01, "{} != {}", y, expected);
+        }
+    }
+
+    #[test]
+    fn biquad_f32_cascade() {
+        let input: Vec<f32> = centered().iter().map(|&x| x as f32).collect();
+        let mut stages = [BIQUAD_LOWPASS; 2];
+        let mut cascade = Cascade::new(&mut stages);
+        for (y, expected) in run(&mut cascade, &input).iter().zip(IIR_EXACT.iter()) {
+            assert!((y - expected).abs() < 0.01, "{} != {}", y, expected);
+        }
+    }
+
+    #[test]
+    fn biquad_q28_cascade() {
+        let mut stages = [BIQUAD_LOWPASS_Q28; 2];
+        let mut cascade = CascadeFixed::new(&mut stages);
+        // Rounding in the feedback path costs up to 2 LSB
+        for (&y, &expected) in run(&mut cascade, &centered()).iter().zip(IIR_EXACT.iter()) {
+            assert!((y as f32 - expected).abs() <= 2.0, "{} != {}", y, expected);
+        }
+    }
+
+    #[test]
+    fn q28_matches_f32_design() {
+        let fixed = BIQUAD_LOWPASS_Q28
+            .b
+            .iter()
+            .chain(BIQUAD_LOWPASS_Q28.a.iter());
+        let float = BIQUAD_LOWPASS.b.iter().chain(BIQUAD_LOWPASS.a.iter());
+        for (&q28, &x) in fixed.zip(float) {
+            // Within the precision of the f32 coefficients
+            let q28 = q28 as f64 / (1u32 << 28) as f64;
+            assert!((q28 - x as f64).abs() < 1e-7, "{} != {}", q28, x);
+        }
+    }
+
+    #[test]
+    fn fixed_point_saturates() {
+        let mut history = [0i16; 3];
+        let mut fir = Fir::new(&[16_384, 16_384, 16_384], &mut history);
+        assert_eq!(run(&mut fir, &[i16::MAX; 3]), [16_384, 32_767, 32_767]);
+        assert_eq!(u16::from_i32(-5), 0);
+        assert_eq!(i16::from_i32(-40_000), i16::MIN);
+    }
+}
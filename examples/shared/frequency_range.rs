@@ -0,0 +1,297 @@
+//! Method choice, auto-ranging and capture evaluation of the frequency counter.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// Gate time of the counting method
+pub const GATE_MS: u32 = 100;
+/// Edges in one gate above which counting beats capturing, 100 kHz at 100 ms
+pub const GATED_MIN_EDGES: u32 = 10_000;
+/// Capture resolution aimed for, in timer ticks per period
+pub const CAPTURE_MIN_TICKS: u32 = 10_000;
+/// CR1.URS for captures. In slave reset mode every rising edge is an update event,
+/// with URS clear it would set UIF as well and every capture would look like an overflow.
+pub const CAPTURE_URS: bool = true;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Edges counted over a fixed gate time
+    Gated,
+    /// Period and high time captured in timer ticks
+    Capture,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub millihertz: u64,
+    /// High time over the period, capture only
+    pub duty_permille: Option<u16>,
+    pub method: Method,
+    /// Prescaler the capture was made with
+    pub psc: u32,
+}
+
+/// One period in PWM input mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// Period and high time in timer ticks
+    Period { period: u32, high: u32 },
+    /// The period was longer than the counter
+    Overflow,
+}
+
+impl Capture {
+    /// Reads the capture after the second rising edge. `uif` is SR.UIF, which
+    /// only reports counter overflows with `CAPTURE_URS`.
+    pub fn new(uif: bool, ccr1: u32, ccr2: u32) -> Self {
+        if uif {
+            Capture::Overflow
+        } else {
+            Capture::Period {
+                period: ccr1,
+                high: ccr2,
+            }
+        }
+    }
+}
+
+/// Counting is used if the gate had to be shortened for the counter, or if it saw
+/// enough edges to beat the capture resolution
+pub fn use_gated(gate_ms: u32, edges: u32) -> bool {
+    gate_ms < GATE_MS || edges >= GATED_MIN_EDGES
+}
+
+/// Next prescaler after a capture with `psc`, for a counter that holds `max_ticks`.
+/// `Ok` when the capture is good as is.
+pub fn range(psc: u32, capture: Capture, max_ticks: u32) -> Result<(), u32> {
+    match capture {
+        // Period too long for the counter, slow down
+        Capture::Overflow if psc < 0xFFFF => Err(((psc + 1) * 8 - 1).min(0xFFFF)),
+        Capture::Overflow => Ok(()),
+        // Too few ticks for the resolution, speed up if the period still fits
+        Capture::Period { period, .. } if period < CAPTURE_MIN_TICKS && psc > 0 => {
+            let faster = (psc + 1) / 8;
+            if faster > 0 && period as u64 * 8 < max_ticks as u64 {
+                Err(faster - 1)
+            } else {
+                Ok(())
+            }
+        }
+        Capture::Period { .. } => Ok(()),
+    }
+}
+
+/// Frequency from `edges` counted over `gate_ms`
+pub fn gated(edges: u32, gate_ms: u32) -> Measurement {
+    Measurement {
+        millihertz: edges as u64 * 1_000_000 / gate_ms as u64,
+        duty_permille: None,
+        method: Method::Gated,
+        psc: 0,
+    }
+}
+
+/// Frequency and duty cycle of a capture with `psc` on a timer clocked at `timer_clock` Hz.
+/// `None` for an overflow.
+pub fn captured(timer_clock: u32, psc: u32, capture: Capture) -> Option<Measurement> {
+    match capture {
+        Capture::Period { period, high } => {
+            let period = period.max(1) as u64;
+            let period_ticks = (psc as u64 + 1) * period;
+            Some(Measurement {
+                millihertz: timer_clock as u64 * 1_000 / period_ticks,
+                duty_permille: Some((high as u64 * 1_000 / period) as u16),
+                method: Method::Capture,
+                psc,
+            })
+        }
+        Capture::Overflow => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK: u32 = 48_000_000;
+
+    /// Counter in slave reset mode with IC1 on the rising and IC2 on the falling edge
+    struct Timer {
+        max: u32,
+        psc: u32,
+        urs: bool,
+        cnt: u32,
+        uif: bool,
+        ccr1: u32,
+        ccr2: u32,
+    }
+
+    impl Timer {
+        fn new(max: u32, psc: u32) -> Self {
+            Timer {
+                max,
+                psc,
+                urs: CAPTURE_URS,
+                cnt: 0,
+                uif: false,
+                ccr1: 0,
+                ccr2: 0,
+            }
+        }
+
+        /// Counts for `clocks` timer clock cycles
+        fn run(&mut self, clocks: u64) {
+            let total = self.cnt as u64 + clocks / (self.psc as u64 + 1);
+            if total > self.max as u64 {
+                self.uif = true;
+            }
+            self.cnt = (total % (self.max as u64 + 1)) as u32;
+        }
+
+        fn rising(&mut self) {
+            self.ccr1 = self.cnt;
+            // The trigger reinitializes the counter with an update event
+            self.cnt = 0;
+            if !self.urs {
+                self.uif = true;
+            }
+        }
+
+        fn falling(&mut self) {
+            self.ccr2 = self.cnt;
+        }
+
+        /// Same steps as `capture` in `timer_frequency_1.rs`
+        fn capture(&mut self, period: u64, high: u64) -> Capture {
+            self.rising();
+            // Flags cleared after the first edge
+            self.uif = false;
+            self.run(high);
+            self.falling();
+            self.run(period - high);
+            self.rising();
+            Capture::new(self.uif, self.ccr1, self.ccr2)
+        }
+    }
+
+    /// Auto-ranges like `measure` for a signal `period` timer clock cycles long
+    fn autorange(max: u32, period: u64) -> (u32, Capture) {
+        let mut psc = 0;
+        for _ in 0..10 {
+            let capture = Timer::new(max, psc).capture(period, period / 4);
+            match range(psc, capture, max) {
+                Err(next) => psc = next,
+                Ok(()) => return (psc, capture),
+            }
+        }
+        panic!("no range for {} cycles", period);
+    }
+
+    #[test]
+    fn captures_periods_that_fit() {
+        let mut timer = Timer::new(0xFFFF, 0);
+        assert_eq!(
+            timer.capture(38_898, 9_724),
+            Capture::Period {
+                period: 38_898,
+                high: 9_724
+            }
+        );
+        // Again without a reset in between, as the timer keeps running
+        assert_eq!(
+            timer.capture(38_898, 9_724),
+            Capture::Period {
+                period: 38_898,
+                high: 9_724
+            }
+        );
+    }
+
+    #[test]
+    fn update_on_reset_would_hide_every_capture() {
+        let mut timer = Timer::new(0xFFFF, 0);
+        timer.urs = false;
+        assert_eq!(timer.capture(1_000, 500), Capture::Overflow);
+    }
+
+    #[test]
+    fn long_periods_overflow() {
+        assert_eq!(
+            Timer::new(0xFFFF, 0).capture(0x1_0000, 100),
+            Capture::Overflow
+        );
+        assert_eq!(
+            Timer::new(0xFFFF, 7).capture(0x8_0000, 800),
+            Capture::Overflow
+        );
+        assert_ne!(
+            Timer::new(0xFFFF, 7).capture(0x7_FFF8, 800),
+            Capture::Overflow
+        );
+    }
+
+    #[test]
+    fn slow_signals_range_down() {
+        // 1 Hz on the 16 bit timer needs a prescaler, then keeps the resolution
+        let (psc, capture) = autorange(0xFFFF, CLOCK as u64);
+        assert_eq!(psc, 4_095);
+        let m = captured(CLOCK, psc, capture).unwrap();
+        assert!(m.millihertz.abs_diff(1_000) <= 1, "{} mHz", m.millihertz);
+        assert!(m.duty_permille.unwrap().abs_diff(250) <= 1);
+        // The 32 bit one gets there without
+        assert_eq!(autorange(0xFFFF_FFFF, CLOCK as u64).0, 0);
+    }
+
+    #[test]
+    fn ranges_back_up_for_resolution() {
+        let mut psc = 4_095;
+        let mut capture = Timer::new(0xFFFF, psc).capture(480_000, 120_000);
+        // 100 Hz, 117 ticks at PSC 4095
+        while let Err(next) = range(psc, capture, 0xFFFF) {
+            psc = next;
+            capture = Timer::new(0xFFFF, psc).capture(480_000, 120_000);
+        }
+        assert_eq!(psc, 7);
+        match capture {
+            Capture::Period { period, .. } => assert!(period >= CAPTURE_MIN_TICKS),
+            Capture::Overflow => panic!("overflowed at PSC {}", psc),
+        }
+    }
+
+    #[test]
+    fn ranging_never_oscillates() {
+        for &max in [0xFFFF, 0xFFFF_FFFF].iter() {
+            let mut period = 1_000;
+            while period < 1 << 40 {
+                // `autorange` panics if it doesn't settle
+                let (psc, capture) = autorange(max, period);
+                match capture {
+                    // Short of the resolution only if 8 times faster would overflow
+                    Capture::Period { period: ticks, .. } => assert!(
+                        ticks >= CAPTURE_MIN_TICKS || psc == 0 || ticks as u64 * 8 >= max as u64,
+                        "{} cycles",
+                        period
+                    ),
+                    Capture::Overflow => assert_eq!(psc, 0xFFFF, "{} cycles", period),
+                }
+                period = period * 3 / 2;
+            }
+        }
+    }
+
+    #[test]
+    fn gives_up_at_the_largest_prescaler() {
+        assert_eq!(range(0xFFFF, Capture::Overflow, 0xFFFF), Ok(()));
+        assert_eq!(range(0x2000, Capture::Overflow, 0xFFFF), Err(0xFFFF));
+        assert!(captured(CLOCK, 0xFFFF, Capture::Overflow).is_none());
+    }
+
+    #[test]
+    fn picks_the_method() {
+        assert!(use_gated(GATE_MS, GATED_MIN_EDGES));
+        assert!(!use_gated(GATE_MS, GATED_MIN_EDGES - 1));
+        // A shortened gate means the counter was about to wrap
+        assert!(use_gated(GATE_MS / 10, 100));
+        let m = gated(123_456, GATE_MS);
+        assert_eq!(m.millihertz, 1_234_560_000);
+        assert_eq!(m.method, Method::Gated);
+    }
+}
@@ -0,0 +1,293 @@
+//! Button gesture recognition from timestamped edges.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Short press, reported once the double click window has passed
+    Click,
+    /// Second short press within `double_click_ms` of the first release
+    DoubleClick,
+    /// Held for `long_press_ms`
+    LongPress,
+    /// Still held, every `repeat_interval_ms` after `repeat_delay_ms`
+    Repeat,
+}
+
+/// Timing thresholds in ms
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Max gap between the first release and the second press.
+    /// 0 reports clicks right on release and never double clicks.
+    pub double_click_ms: u32,
+    pub long_press_ms: u32,
+    /// From the long press to the first repeat. 0 disables repeats.
+    pub repeat_delay_ms: u32,
+    pub repeat_interval_ms: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            double_click_ms: 300,
+            long_press_ms: 800,
+            repeat_delay_ms: 500,
+            repeat_interval_ms: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// Down since `since`, `second` is the second press of a double click
+    Pressed {
+        since: u32,
+        second: bool,
+    },
+    /// Released a short press at `at`, waiting for a second one
+    Released {
+        at: u32,
+    },
+    /// Long press reported, next repeat at `next`
+    Held {
+        next: u32,
+    },
+}
+
+/// Feed it button edges with `edge` and call `poll` regularly for the
+/// time based gestures. Timestamps are in ms and may wrap around.
+pub struct Recognizer {
+    config: Config,
+    state: State,
+}
+
+impl Recognizer {
+    pub fn new(config: Config) -> Self {
+        Recognizer {
+            config,
+            state: State::Idle,
+        }
+    }
+
+    /// Button went down (`pressed`) or up at `now`
+    pub fn edge(&mut self, pressed: bool, now: u32) -> Option<Gesture> {
+        // Catch up on timeouts first, in case `poll` lagged behind
+        let timeout = self.poll(now);
+        let (state, gesture) = match (self.state, pressed) {
+            (State::Idle, true) => (
+                State::Pressed {
+                    since: now,
+                    second: false,
+                },
+                None,
+            ),
+            (State::Released { .. }, true) => (
+                State::Pressed {
+                    since: now,
+                    second: true,
+                },
+                None,
+            ),
+            (State::Pressed { second: true, .. }, false) => {
+                (State::Idle, Some(Gesture::DoubleClick))
+            }
+            (State::Pressed { second: false, .. }, false) => {
+                if self.config.double_click_ms == 0 {
+                    (State::Idle, Some(Gesture::Click))
+                } else {
+                    (State::Released { at: now }, None)
+                }
+            }
+            (State::Held { .. }, false) => (State::Idle, None),
+            // Repeated level, nothing changed
+            (state, _) => (state, None),
+        };
+        self.state = state;
+        timeout.or(gesture)
+    }
+
+    /// Reports gestures that complete by time passing
+    pub fn poll(&mut self, now: u32) -> Option<Gesture> {
+        match self.state {
+            State::Pressed { since, .. }
+                if now.wrapping_sub(since) >= self.config.long_press_ms =>
+            {
+                self.state = State::Held {
+                    next: since
+                        .wrapping_add(self.config.long_press_ms)
+                        .wrapping_add(self.config.repeat_delay_ms),
+                };
+                Some(Gesture::LongPress)
+            }
+            State::Released { at } if now.wrapping_sub(at) > self.config.double_click_ms => {
+                self.state = State::Idle;
+                Some(Gesture::Click)
+            }
+            State::Held { next } if self.config.repeat_delay_ms > 0 && !is_before(now, next) => {
+                self.state = State::Held {
+                    next: next.wrapping_add(self.config.repeat_interval_ms.max(1)),
+                };
+                Some(Gesture::Repeat)
+            }
+            _ => None,
+        }
+    }
+
+    /// True while the button is down
+    pub fn is_pressed(&self) -> bool {
+        matches!(self.state, State::Pressed { .. } | State::Held { .. })
+    }
+
+    /// Drops any gesture in progress
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+}
+
+/// Wrapping time comparison, valid for differences up to 2^31 ms
+fn is_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drives the recognizer like the firmware: the edges at their times and a
+    /// poll on every other ms tick. Returns the gestures with the time they came.
+    fn simulate(
+        config: Config,
+        start: u32,
+        edges: &[(u32, bool)],
+        until: u32,
+    ) -> Vec<(u32, Gesture)> {
+        let mut recognizer = Recognizer::new(config);
+        let mut gestures = Vec::new();
+        for t in 0..=until {
+            let now = start.wrapping_add(t);
+            let gesture = match edges.iter().find(|&&(at, _)| at == t) {
+                Some(&(_, pressed)) => recognizer.edge(pressed, now),
+                None => recognizer.poll(now),
+            };
+            if let Some(gesture) = gesture {
+                gestures.push((t, gesture));
+            }
+        }
+        gestures
+    }
+
+    #[test]
+    fn tap_is_a_click_after_the_double_click_window() {
+        let gestures = simulate(Config::default(), 0, &[(0, true), (100, false)], 2_000);
+        assert_eq!(gestures, [(401, Gesture::Click)]);
+    }
+
+    #[test]
+    fn double_tap() {
+        let edges = [(0, true), (100, false), (250, true), (330, false)];
+        let gestures = simulate(Config::default(), 0, &edges, 2_000);
+        assert_eq!(gestures, [(330, Gesture::DoubleClick)]);
+    }
+
+    #[test]
+    fn taps_too_far_apart_are_two_clicks() {
+        let edges = [(0, true), (100, false), (500, true), (600, false)];
+        let gestures = simulate(Config::default(), 0, &edges, 2_000);
+        assert_eq!(gestures, [(401, Gesture::Click), (901, Gesture::Click)]);
+    }
+
+    #[test]
+    fn long_press_and_repeats() {
+        let gestures = simulate(Config::default(), 0, &[(0, true), (1_750, false)], 3_000);
+        assert_eq!(
+            gestures,
+            [
+                (800, Gesture::LongPress),
+                (1_300, Gesture::Repeat),
+                (1_500, Gesture::Repeat),
+                (1_700, Gesture::Repeat),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_can_be_disabled() {
+        let config = Config {
+            repeat_delay_ms: 0,
+            ..Config::default()
+        };
+        let gestures = simulate(config, 0, &[(0, true), (3_000, false)], 4_000);
+        assert_eq!(gestures, [(800, Gesture::LongPress)]);
+    }
+
+    #[test]
+    fn second_press_held_long_is_a_long_press() {
+        let edges = [(0, true), (100, false), (200, true), (1_100, false)];
+        let gestures = simulate(Config::default(), 0, &edges, 2_000);
+        assert_eq!(gestures, [(1_000, Gesture::LongPress)]);
+    }
+
+    #[test]
+    fn clicks_on_release_without_a_double_click_window() {
+        let config = Config {
+            double_click_ms: 0,
+            ..Config::default()
+        };
+        let edges = [(0, true), (100, false), (150, true), (200, false)];
+        let gestures = simulate(config, 0, &edges, 1_000);
+        assert_eq!(gestures, [(100, Gesture::Click), (200, Gesture::Click)]);
+    }
+
+    #[test]
+    fn late_edges_catch_up_on_timeouts() {
+        // No polls at all, the next press reports the click that timed out
+        let mut recognizer = Recognizer::new(Config::default());
+        assert_eq!(recognizer.edge(true, 0), None);
+        assert_eq!(recognizer.edge(false, 100), None);
+        assert_eq!(recognizer.edge(true, 1_000), Some(Gesture::Click));
+        assert!(recognizer.is_pressed());
+        assert_eq!(recognizer.edge(false, 1_100), None);
+        assert_eq!(recognizer.poll(1_401), Some(Gesture::Click));
+    }
+
+    #[test]
+    fn repeated_levels_are_ignored() {
+        let mut recognizer = Recognizer::new(Config::default());
+        assert_eq!(recognizer.edge(false, 0), None);
+        assert_eq!(recognizer.edge(true, 10), None);
+        assert_eq!(recognizer.edge(true, 20), None);
+        // The long press still counts from the first press
+        assert_eq!(recognizer.poll(810), Some(Gesture::LongPress));
+    }
+
+    #[test]
+    fn reset_drops_the_gesture_in_progress() {
+        let mut recognizer = Recognizer::new(Config::default());
+        recognizer.edge(true, 0);
+        recognizer.edge(false, 100);
+        recognizer.reset();
+        assert_eq!(recognizer.poll(1_000), None);
+        assert!(!recognizer.is_pressed());
+    }
+
+    #[test]
+    fn timestamps_wrap_around() {
+        let start = u32::MAX - 500;
+        let edges = [
+            (0, true),
+            (100, false),
+            (250, true),
+            (330, false),
+            (600, true),
+        ];
+        let gestures = simulate(Config::default(), start, &edges, 2_000);
+        assert_eq!(
+            gestures,
+            [
+                (330, Gesture::DoubleClick),
+                (1_400, Gesture::LongPress),
+                (1_900, Gesture::Repeat),
+            ]
+        );
+    }
+}
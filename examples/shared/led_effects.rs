@@ -0,0 +1,284 @@
+//! Colors and frame-by-frame LED effects: fades, breathing, blinking, rainbow.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// 8 bit per channel color in perceived brightness
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0);
+    pub const WHITE: Color = Color::new(255, 255, 255);
+    pub const RED: Color = Color::new(255, 0, 0);
+    pub const GREEN: Color = Color::new(0, 255, 0);
+    pub const BLUE: Color = Color::new(0, 0, 255);
+    pub const YELLOW: Color = Color::new(255, 255, 0);
+    pub const CYAN: Color = Color::new(0, 255, 255);
+    pub const MAGENTA: Color = Color::new(255, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    /// Blend towards `other`, `t` from 0 (self) to 255 (other)
+    pub fn mix(self, other: Color, t: u8) -> Color {
+        let lerp = |a: u8, b: u8| {
+            // Weighted sum rather than a + (b - a) * t, which rounds towards a on the way down
+            let (a, b, t) = (a as u32, b as u32, t as u32);
+            ((a * (255 - t) + b * t + 127) / 255) as u8
+        };
+        Color::new(
+            lerp(self.r, other.r),
+            lerp(self.g, other.g),
+            lerp(self.b, other.b),
+        )
+    }
+
+    /// Dims to `level` / 255
+    pub fn scale(self, level: u8) -> Color {
+        Color::BLACK.mix(self, level)
+    }
+
+    /// Fully saturated color around the hue circle, 0..=255 is one turn
+    pub fn wheel(hue: u8) -> Color {
+        // Three 85 step segments: red -> green -> blue -> red
+        let hue = hue.min(254);
+        let segment = hue / 85;
+        let t = (hue % 85) * 3;
+        match segment {
+            0 => Color::new(255 - t, t, 0),
+            1 => Color::new(0, 255 - t, t),
+            _ => Color::new(t, 0, 255 - t),
+        }
+    }
+}
+
+/// Durations are in frames
+#[derive(Debug, Clone, Copy)]
+pub enum Effect {
+    /// Holds a color
+    Solid(Color),
+    /// Cross-fades from the current color, then holds
+    Fade {
+        to: Color,
+        frames: u16,
+    },
+    /// Rises and falls between off and `color`
+    Breathe {
+        color: Color,
+        period: u16,
+    },
+    Blink {
+        color: Color,
+        on: u16,
+        off: u16,
+    },
+    /// Cycles through the hue circle
+    Rainbow {
+        period: u16,
+    },
+}
+
+/// Renders an effect frame by frame
+pub struct Engine {
+    effect: Effect,
+    frame: u32,
+    /// Last rendered color, where fades start from
+    current: Color,
+    from: Color,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            effect: Effect::Solid(Color::BLACK),
+            frame: 0,
+            current: Color::BLACK,
+            from: Color::BLACK,
+        }
+    }
+
+    /// Replaces the running effect. Takes over from the current color without a jump.
+    pub fn start(&mut self, effect: Effect) {
+        self.effect = effect;
+        self.frame = 0;
+        self.from = self.current;
+    }
+
+    /// True once a fade has reached its target. Other effects never finish.
+    pub fn is_done(&self) -> bool {
+        match self.effect {
+            Effect::Solid(_) => true,
+            Effect::Fade { frames, .. } => self.frame >= frames as u32,
+            _ => false,
+        }
+    }
+
+    /// Advances one frame and returns the color to show
+    pub fn tick(&mut self) -> Color {
+        let frame = self.frame;
+        self.frame = self.frame.saturating_add(1);
+        self.current = match self.effect {
+            Effect::Solid(color) => color,
+            Effect::Fade { to, frames } => {
+                if frame >= frames as u32 {
+                    to
+                } else {
+                    self.from.mix(to, (frame * 255 / frames as u32) as u8)
+                }
+            }
+            Effect::Breathe { color, period } => {
+                // Triangle wave. Linear in perceived brightness, gamma makes it look smooth.
+                let period = period.max(2) as u32;
+                let phase = frame % period;
+                let half = period / 2;
+                let level = if phase < half {
+                    phase * 255 / half
+                } else {
+                    (period - phase) * 255 / (period - half)
+                };
+                color.scale(level as u8)
+            }
+            Effect::Blink { color, on, off } => {
+                if frame % (on as u32 + off as u32).max(1) < on as u32 {
+                    color
+                } else {
+                    Color::BLACK
+                }
+            }
+            Effect::Rainbow { period } => {
+                let period = period.max(1) as u32;
+                Color::wheel((frame % period * 256 / period) as u8)
+            }
+        };
+        self.current
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colors of the next `frames` frames
+    fn run(engine: &mut Engine, frames: usize) -> Vec<Color> {
+        (0..frames).map(|_| engine.tick()).collect()
+    }
+
+    #[test]
+    fn mix_and_scale() {
+        assert_eq!(Color::RED.mix(Color::BLUE, 0), Color::RED);
+        assert_eq!(Color::RED.mix(Color::BLUE, 255), Color::BLUE);
+        assert_eq!(Color::RED.mix(Color::BLUE, 128), Color::new(127, 0, 128));
+        assert_eq!(Color::WHITE.scale(0), Color::BLACK);
+        assert_eq!(Color::WHITE.scale(255), Color::WHITE);
+        assert_eq!(Color::YELLOW.scale(51), Color::new(51, 51, 0));
+    }
+
+    #[test]
+    fn wheel_is_continuous() {
+        assert_eq!(Color::wheel(0), Color::RED);
+        assert_eq!(Color::wheel(85), Color::GREEN);
+        assert_eq!(Color::wheel(170), Color::BLUE);
+        // Small steps all the way around, over the wrap too
+        for hue in 0..=255u8 {
+            let (a, b) = (Color::wheel(hue), Color::wheel(hue.wrapping_add(1)));
+            assert!(a.r.abs_diff(b.r) <= 3, "hue {}", hue);
+            assert!(a.g.abs_diff(b.g) <= 3, "hue {}", hue);
+            assert!(a.b.abs_diff(b.b) <= 3, "hue {}", hue);
+        }
+    }
+
+    #[test]
+    fn fade_reaches_its_target_and_holds() {
+        let mut engine = Engine::new();
+        engine.start(Effect::Fade {
+            to: Color::RED,
+            frames: 100,
+        });
+        let colors = run(&mut engine, 100);
+        assert_eq!(colors[0], Color::BLACK);
+        assert!(colors.windows(2).all(|w| w[1].r >= w[0].r));
+        // The target is shown once all the frames are through
+        assert_ne!(colors[99], Color::RED);
+        assert!(engine.is_done());
+        assert_eq!(run(&mut engine, 10), [Color::RED; 10]);
+    }
+
+    #[test]
+    fn new_effects_take_over_without_a_jump() {
+        let mut engine = Engine::new();
+        engine.start(Effect::Fade {
+            to: Color::RED,
+            frames: 100,
+        });
+        let halfway = *run(&mut engine, 50).last().unwrap();
+        engine.start(Effect::Fade {
+            to: Color::BLUE,
+            frames: 100,
+        });
+        assert!(!engine.is_done());
+        assert_eq!(engine.tick(), halfway);
+        let last = *run(&mut engine, 100).last().unwrap();
+        assert_eq!(last, Color::BLUE);
+    }
+
+    #[test]
+    fn breathe_rises_and_falls() {
+        let mut engine = Engine::new();
+        engine.start(Effect::Breathe {
+            color: Color::CYAN,
+            period: 300,
+        });
+        let colors = run(&mut engine, 301);
+        assert_eq!(colors[0], Color::BLACK);
+        assert_eq!(colors[150], Color::CYAN);
+        assert!(colors[..=150].windows(2).all(|w| w[1].g >= w[0].g));
+        assert!(colors[150..300].windows(2).all(|w| w[1].g <= w[0].g));
+        // Next period
+        assert_eq!(colors[300], Color::BLACK);
+        assert!(!engine.is_done());
+    }
+
+    #[test]
+    fn blink_follows_the_pattern() {
+        let mut engine = Engine::new();
+        engine.start(Effect::Blink {
+            color: Color::WHITE,
+            on: 10,
+            off: 40,
+        });
+        let colors = run(&mut engine, 100);
+        for (frame, &color) in colors.iter().enumerate() {
+            let on = frame % 50 < 10;
+            assert_eq!(color == Color::WHITE, on, "frame {}", frame);
+            assert_eq!(color == Color::BLACK, !on, "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn rainbow_turns_once_per_period() {
+        let mut engine = Engine::new();
+        engine.start(Effect::Rainbow { period: 500 });
+        let colors = run(&mut engine, 501);
+        assert_eq!(colors[0], Color::RED);
+        assert_eq!(colors[500], Color::RED);
+        assert_eq!(colors[250], Color::wheel(128));
+    }
+
+    #[test]
+    fn solid_is_done_right_away() {
+        let mut engine = Engine::new();
+        engine.start(Effect::Solid(Color::MAGENTA));
+        assert!(engine.is_done());
+        assert_eq!(engine.tick(), Color::MAGENTA);
+    }
+}
@@ -0,0 +1,276 @@
+//! Commutation tables and space-vector modulation for three-phase motors.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// State of one half bridge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// PWM on the high side, complementary on the low side
+    High,
+    /// Low side on
+    Low,
+    /// Both switches off
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+use self::Phase::{Float as F, High as H, Low as L};
+
+/// Phases A, B, C for each hall state H3 H2 H1, 120° sensor placement.
+/// Swap two hall wires if the motor runs backwards or stalls.
+const FORWARD: [Option<[Phase; 3]>; 8] = [
+    None,
+    Some([F, L, H]), // 001
+    Some([L, H, F]), // 010
+    Some([L, F, H]), // 011
+    Some([H, F, L]), // 100
+    Some([H, L, F]), // 101
+    Some([F, H, L]), // 110
+    None,
+];
+
+/// Phase states for a hall state, `None` for the invalid states 000 and 111
+pub fn commutation(hall: u8, direction: Direction) -> Option<[Phase; 3]> {
+    let phases = FORWARD[(hall & 0b111) as usize]?;
+    Some(match direction {
+        Direction::Forward => phases,
+        // Reversing swaps the driven high and low sides
+        Direction::Reverse => {
+            let mut reversed = phases;
+            for phase in reversed.iter_mut() {
+                *phase = match *phase {
+                    H => L,
+                    L => H,
+                    F => F,
+                };
+            }
+            reversed
+        }
+    })
+}
+
+/// First quadrant of sin(x) in Q15, 64 steps
+const QUARTER_SINE: [i16; 65] = [
+    0, 804, 1608, 2410, 3212, 4011, 4808, 5602, //
+    6393, 7179, 7962, 8739, 9512, 10278, 11039, 11793, //
+    12539, 13279, 14010, 14732, 15446, 16151, 16846, 17530, //
+    18204, 18868, 19519, 20159, 20787, 21403, 22005, 22594, //
+    23170, 23731, 24279, 24811, 25329, 25832, 26319, 26790, //
+    27245, 27683, 28105, 28510, 28898, 29268, 29621, 29956, //
+    30273, 30571, 30852, 31113, 31356, 31580, 31785, 31971, //
+    32137, 32285, 32412, 32521, 32609, 32678, 32728, 32757, //
+    32767,
+];
+
+/// sin(angle) in Q15, a full turn is 65536
+pub fn sin(angle: u16) -> i16 {
+    let x = angle & 0x3FFF;
+    let quadrant = angle >> 14;
+    // Second and fourth quadrants run the table backwards
+    let x = if quadrant & 1 == 1 { 0x4000 - x } else { x };
+    let x = x as i32;
+    // Linear interpolation between table entries 256 apart
+    let i = (x >> 8) as usize;
+    let frac = x & 0xFF;
+    let y0 = QUARTER_SINE[i] as i32;
+    let y = if i < 64 {
+        y0 + (((QUARTER_SINE[i + 1] as i32 - y0) * frac) >> 8)
+    } else {
+        y0
+    };
+    if quadrant >= 2 {
+        -y as i16
+    } else {
+        y as i16
+    }
+}
+
+/// 1/√3 in Q15
+const INV_SQRT3: i32 = 18919;
+/// 120° as a fraction of 65536
+const THIRD: u16 = 21845;
+
+/// Space-vector PWM by min-max injection. `amplitude` is Q15, where 32767 is the largest
+/// undistorted output. Returns duties for phases A, B, C in Q15 (0..=32767).
+pub fn svpwm(angle: u16, amplitude: i16) -> [u16; 3] {
+    let m = amplitude.max(0) as i32;
+    let va = (m * sin(angle) as i32) >> 15;
+    let vb = (m * sin(angle.wrapping_sub(THIRD)) as i32) >> 15;
+    let vc = (m * sin(angle.wrapping_add(THIRD)) as i32) >> 15;
+    // Shift the common mode so the largest and smallest phases are centered
+    let max = va.max(vb).max(vc);
+    let min = va.min(vb).min(vc);
+    let offset = -(max + min) / 2;
+    let duty = |v: i32| (16384 + (((v + offset) * INV_SQRT3) >> 15)).clamp(0, 32767) as u16;
+    [duty(va), duty(vb), duty(vc)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hall states in the order a forward turning motor produces them
+    const HALL_SEQUENCE: [u8; 6] = [0b001, 0b011, 0b010, 0b110, 0b100, 0b101];
+
+    #[test]
+    fn every_valid_hall_state_drives_one_high_and_one_low() {
+        for &hall in HALL_SEQUENCE.iter() {
+            for &direction in [Direction::Forward, Direction::Reverse].iter() {
+                let phases = commutation(hall, direction).unwrap();
+                let count = |state| phases.iter().filter(|&&p| p == state).count();
+                assert_eq!((count(H), count(L), count(F)), (1, 1, 1), "{:03b}", hall);
+            }
+        }
+    }
+
+    #[test]
+    fn forward_table() {
+        let steps: Vec<[Phase; 3]> = HALL_SEQUENCE
+            .iter()
+            .map(|&hall| commutation(hall, Direction::Forward).unwrap())
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                [F, L, H],
+                [L, F, H],
+                [L, H, F],
+                [F, H, L],
+                [H, F, L],
+                [H, L, F],
+            ]
+        );
+    }
+
+    #[test]
+    fn successive_steps_move_the_floating_phase() {
+        for (i, &hall) in HALL_SEQUENCE.iter().enumerate() {
+            let next = HALL_SEQUENCE[(i + 1) % 6];
+            let a = commutation(hall, Direction::Forward).unwrap();
+            let b = commutation(next, Direction::Forward).unwrap();
+            // One phase keeps its state, the floating phase moves on
+            assert_eq!(a.iter().zip(b.iter()).filter(|(x, y)| x == y).count(), 1);
+            assert_ne!(
+                a.iter().position(|&p| p == F),
+                b.iter().position(|&p| p == F)
+            );
+        }
+    }
+
+    #[test]
+    fn reverse_swaps_high_and_low() {
+        for &hall in HALL_SEQUENCE.iter() {
+            let forward = commutation(hall, Direction::Forward).unwrap();
+            let reverse = commutation(hall, Direction::Reverse).unwrap();
+            for (f, r) in forward.iter().zip(reverse.iter()) {
+                let expected = match f {
+                    H => L,
+                    L => H,
+                    F => F,
+                };
+                assert_eq!(*r, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_hall_states_coast() {
+        for &direction in [Direction::Forward, Direction::Reverse].iter() {
+            assert_eq!(commutation(0b000, direction), None);
+            assert_eq!(commutation(0b111, direction), None);
+        }
+        // Only the low three bits are hall inputs
+        assert_eq!(
+            commutation(0b1001, Direction::Forward),
+            commutation(0b001, Direction::Forward)
+        );
+    }
+
+    #[test]
+    fn sine_matches_reference() {
+        assert_eq!(sin(0), 0);
+        assert_eq!(sin(0x4000), 32767);
+        assert_eq!(sin(0x8000), 0);
+        assert_eq!(sin(0xC000), -32767);
+        for angle in (0..=0xFFFFu32).step_by(7) {
+            let expected = (angle as f64 / 65536.0 * 2.0 * std::f64::consts::PI).sin() * 32767.0;
+            let error = (sin(angle as u16) as f64 - expected).abs();
+            assert!(error < 8.0, "sin({}) off by {}", angle, error);
+        }
+    }
+
+    #[test]
+    fn svpwm_centers_the_outer_phases() {
+        for &amplitude in [0, 8_000, 20_000, 32_767].iter() {
+            for angle in (0..=0xFFFFu32).step_by(13) {
+                let duties = svpwm(angle as u16, amplitude);
+                let max = *duties.iter().max().unwrap() as i32;
+                let min = *duties.iter().min().unwrap() as i32;
+                // Min-max injection puts the largest and smallest duty symmetric around 50 %
+                assert!((max + min - 32_768).abs() <= 2, "{:?}", duties);
+            }
+        }
+        assert_eq!(svpwm(12_345, 0), [16_384; 3]);
+        // Negative amplitudes are treated as 0
+        assert_eq!(svpwm(12_345, -100), [16_384; 3]);
+    }
+
+    #[test]
+    fn svpwm_line_voltages_follow_the_amplitude() {
+        for &amplitude in [8_000i16, 32_767].iter() {
+            let mut peak = 0;
+            for angle in (0..=0xFFFFu32).step_by(5) {
+                let [a, b, _] = svpwm(angle as u16, amplitude);
+                peak = peak.max((a as i32 - b as i32).abs());
+            }
+            // The line-to-line duty swings by the amplitude, so full scale reaches both rails
+            assert!(
+                (peak - amplitude as i32).abs() <= 16,
+                "{} vs {}",
+                peak,
+                amplitude
+            );
+        }
+    }
+
+    #[test]
+    fn svpwm_sectors() {
+        const SIXTH: u32 = 65_536 / 6;
+        // Phases A, B, C sorted from highest to lowest duty in each 60° sector.
+        // The sectors start at 30°, where A overtakes C.
+        let order = |angle: u32| {
+            let duties = svpwm((angle % 65_536) as u16, 20_000);
+            let mut phases = [0, 1, 2];
+            phases.sort_by_key(|&i| std::cmp::Reverse(duties[i]));
+            phases
+        };
+        let sectors = [
+            [0, 2, 1],
+            [0, 1, 2],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 1, 0],
+            [2, 0, 1],
+        ];
+        for (k, expected) in sectors.iter().enumerate() {
+            let start = (65_536 * (2 * k as u32 + 1) + 6) / 12;
+            // The two phases that swap places are equal on the boundary
+            let previous = sectors[(k + 5) % 6];
+            let swapped: Vec<usize> = (0..3)
+                .filter(|&i| previous[i] != expected[i])
+                .map(|i| expected[i])
+                .collect();
+            let duties = svpwm((start % 65_536) as u16, 20_000);
+            let difference = duties[swapped[0]] as i32 - duties[swapped[1]] as i32;
+            assert!(difference.abs() <= 2, "{:?} at {}", duties, start);
+            // Stay clear of the boundaries for the order
+            for angle in (start + 200..start + SIXTH - 200).step_by(50) {
+                assert_eq!(order(angle), *expected, "angle {}", angle);
+            }
+        }
+    }
+}
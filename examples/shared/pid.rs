@@ -0,0 +1,297 @@
+//! PID controller for a fixed-rate loop.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// PID controller for a fixed-rate loop.
+///
+/// - The integrator stops while the output is saturated in the direction of the error (anti-windup).
+/// - The derivative acts on the measurement through a first order low-pass, so setpoint
+///   steps don't kick the output.
+/// - Setpoint changes can be slew rate limited. The ramp starts from the first measurement,
+///   and `reset` preloads the integrator for bumpless transfer from manual control.
+#[derive(Debug, Clone, Copy)]
+pub struct Pid {
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    dt: f32,
+    out_min: f32,
+    out_max: f32,
+    /// Derivative low-pass time constant in seconds
+    tau: f32,
+    /// Maximum setpoint change per second, 0 disables the ramp
+    ramp: f32,
+    target: f32,
+    setpoint: f32,
+    /// Output contribution of the integral term
+    integral: f32,
+    derivative: f32,
+    last_error: f32,
+    last_measurement: Option<f32>,
+}
+
+impl Pid {
+    /// `dt` is the loop period in seconds
+    pub fn new(kp: f32, ki: f32, kd: f32, dt: f32) -> Self {
+        Pid {
+            kp,
+            ki,
+            kd,
+            dt,
+            out_min: f32::NEG_INFINITY,
+            out_max: f32::INFINITY,
+            tau: 0.0,
+            ramp: 0.0,
+            target: 0.0,
+            setpoint: 0.0,
+            integral: 0.0,
+            derivative: 0.0,
+            last_error: 0.0,
+            last_measurement: None,
+        }
+    }
+
+    /// Clamps the output to `min..=max`
+    pub fn output_limits(self, min: f32, max: f32) -> Self {
+        Pid {
+            out_min: min,
+            out_max: max,
+            ..self
+        }
+    }
+
+    /// Low-pass the derivative term with time constant `tau` seconds
+    pub fn derivative_filter(self, tau: f32) -> Self {
+        Pid { tau, ..self }
+    }
+
+    /// Limits setpoint changes to `rate` units per second
+    pub fn setpoint_ramp(self, rate: f32) -> Self {
+        Pid { ramp: rate, ..self }
+    }
+
+    /// Sets a new target. The effective setpoint follows at the ramp rate.
+    pub fn set_setpoint(&mut self, setpoint: f32) {
+        self.target = setpoint;
+        if self.ramp <= 0.0 {
+            self.setpoint = setpoint;
+        }
+    }
+
+    /// Effective setpoint after ramping
+    pub fn setpoint(&self) -> f32 {
+        self.setpoint
+    }
+
+    /// Changes gains without a jump in the output: the integral takes up the
+    /// difference in the proportional and derivative terms of the last update
+    pub fn set_gains(&mut self, kp: f32, ki: f32, kd: f32) {
+        // The integral is stored as output contribution, so it stays valid when ki changes
+        self.integral += (self.kp - kp) * self.last_error + (self.kd - kd) * self.derivative;
+        self.kp = kp;
+        self.ki = ki;
+        self.kd = kd;
+    }
+
+    /// Bumpless transfer: the next `update` with the same measurement returns `output`
+    pub fn reset(&mut self, measurement: f32, output: f32) {
+        self.setpoint = measurement;
+        self.target = measurement;
+        self.derivative = 0.0;
+        self.last_error = 0.0;
+        self.last_measurement = Some(measurement);
+        self.integral = self.clamp(output);
+    }
+
+    /// Runs one step of the loop and returns the clamped output
+    pub fn update(&mut self, measurement: f32) -> f32 {
+        if self.last_measurement.is_none() && self.ramp > 0.0 {
+            // Ramp from where the plant is rather than from 0
+            self.setpoint = measurement;
+        }
+        self.ramp_setpoint();
+        let error = self.setpoint - measurement;
+
+        // Derivative on measurement, low-passed
+        let last = self.last_measurement.unwrap_or(measurement);
+        let raw = -(measurement - last) / self.dt;
+        let alpha = self.dt / (self.tau + self.dt);
+        self.derivative += alpha * (raw - self.derivative);
+        self.last_measurement = Some(measurement);
+        self.last_error = error;
+
+        let proportional = self.kp * error;
+        let derivative = self.kd * self.derivative;
+        let integral = self.integral + self.ki * error * self.dt;
+
+        let unclamped = proportional + integral + derivative;
+        let output = self.clamp(unclamped);
+        // Integrate only if it doesn't push further into saturation
+        let saturated_high = unclamped > self.out_max && error > 0.0;
+        let saturated_low = unclamped < self.out_min && error < 0.0;
+        if !saturated_high && !saturated_low {
+            self.integral = integral;
+        }
+        output
+    }
+
+    fn ramp_setpoint(&mut self) {
+        let step = self.ramp * self.dt;
+        let delta = self.target - self.setpoint;
+        self.setpoint = if self.ramp <= 0.0 || (-step..=step).contains(&delta) {
+            self.target
+        } else if delta > 0.0 {
+            self.setpoint + step
+        } else {
+            self.setpoint - step
+        };
+    }
+
+    fn clamp(&self, x: f32) -> f32 {
+        x.max(self.out_min).min(self.out_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    /// First order plant, `gain` per unit input with time constant `tau` seconds
+    struct Plant {
+        gain: f32,
+        tau: f32,
+        y: f32,
+    }
+
+    impl Plant {
+        fn step(&mut self, u: f32) -> f32 {
+            self.y += (self.gain * u - self.y) * DT / self.tau;
+            self.y
+        }
+    }
+
+    fn controller() -> Pid {
+        Pid::new(2.0, 20.0, 0.01, DT)
+            .output_limits(0.0, 1.0)
+            .derivative_filter(0.1)
+    }
+
+    /// Runs the loop for `seconds`, returns the last measurement and output
+    fn run(pid: &mut Pid, plant: &mut Plant, seconds: f32) -> (f32, f32) {
+        let mut u = 0.0;
+        for _ in 0..(seconds / DT) as usize {
+            u = pid.update(plant.y);
+            plant.step(u);
+        }
+        (plant.y, u)
+    }
+
+    #[test]
+    fn step_response_settles_on_the_setpoint() {
+        let mut pid = controller();
+        let mut plant = Plant {
+            gain: 1.0,
+            tau: 0.05,
+            y: 0.0,
+        };
+        pid.set_setpoint(0.5);
+        let mut peak = 0.0f32;
+        for _ in 0..2_000 {
+            let u = pid.update(plant.y);
+            assert!((0.0..=1.0).contains(&u));
+            peak = peak.max(plant.step(u));
+        }
+        assert!((plant.y - 0.5).abs() < 0.005, "settled at {}", plant.y);
+        assert!(peak < 0.6, "overshoot to {}", peak);
+    }
+
+    #[test]
+    fn output_is_clamped() {
+        let mut pid = controller();
+        pid.set_setpoint(1_000.0);
+        assert_eq!(pid.update(0.0), 1.0);
+        pid.set_setpoint(-1_000.0);
+        assert_eq!(pid.update(0.0), 0.0);
+    }
+
+    #[test]
+    fn integrator_does_not_wind_up() {
+        let mut pid = controller();
+        // Half the input reaches the output, 0.8 is out of reach
+        let mut plant = Plant {
+            gain: 0.5,
+            tau: 0.05,
+            y: 0.0,
+        };
+        pid.set_setpoint(0.8);
+        let (y, u) = run(&mut pid, &mut plant, 5.0);
+        assert_eq!(u, 1.0);
+        assert!((y - 0.5).abs() < 0.001);
+        assert!(pid.integral <= 1.0, "integral wound up to {}", pid.integral);
+
+        // Without anti-windup 5 s at error 0.3 would hold the output high for over a second
+        pid.set_setpoint(0.2);
+        let steps = (0..1_000)
+            .position(|_| {
+                let u = pid.update(plant.y);
+                plant.step(u);
+                u < 1.0
+            })
+            .unwrap();
+        assert!(steps < 50, "left saturation after {} steps", steps);
+        let (y, _) = run(&mut pid, &mut plant, 2.0);
+        assert!((y - 0.2).abs() < 0.005, "settled at {}", y);
+    }
+
+    #[test]
+    fn setpoint_ramps_from_the_first_measurement() {
+        let mut pid = controller().setpoint_ramp(0.5);
+        pid.set_setpoint(0.75);
+        pid.update(0.25);
+        assert!((pid.setpoint() - (0.25 + 0.5 * DT)).abs() < 1e-6);
+        for _ in 0..999 {
+            pid.update(0.25);
+        }
+        assert!((pid.setpoint() - 0.75).abs() < 1e-4);
+        pid.update(0.25);
+        assert_eq!(pid.setpoint(), 0.75);
+    }
+
+    #[test]
+    fn gain_changes_are_bumpless() {
+        let mut pid = controller();
+        let mut plant = Plant {
+            gain: 1.0,
+            tau: 0.05,
+            y: 0.0,
+        };
+        pid.set_setpoint(0.5);
+        // Mid transient, so every term contributes
+        run(&mut pid, &mut plant, 0.02);
+        // Hold the measurement of the last update, only the gains change
+        let measurement = plant.y;
+        pid.update(measurement);
+        let mut unchanged = pid;
+        pid.set_gains(6.0, 5.0, 0.05);
+        // The proportional term alone would jump by 4 * error
+        assert!(4.0 * pid.last_error > 0.1);
+        let expected = unchanged.update(measurement);
+        let output = pid.update(measurement);
+        // What is left is one step of the new integral and derivative gains
+        assert!(
+            (output - expected).abs() < 0.01,
+            "{} vs {}",
+            output,
+            expected
+        );
+    }
+
+    #[test]
+    fn reset_transfers_bumplessly() {
+        let mut pid = controller();
+        pid.reset(0.3, 0.6);
+        assert!((pid.update(0.3) - 0.6).abs() < 1e-6);
+        assert_eq!(pid.setpoint(), 0.3);
+    }
+}
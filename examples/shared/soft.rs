@@ -0,0 +1,317 @@
+//! One-shot and periodic software timers in a queue sorted by deadline.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// Timers that can run at once
+pub const CAPACITY: usize = 16;
+
+pub type Callback = fn(TimerId);
+
+/// Handle of a started timer. Stays invalid once the timer is done or cancelled,
+/// even if its slot is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    slot: u8,
+    generation: u8,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    deadline: u32,
+    /// 0 for one-shot timers
+    period: u32,
+    callback: Callback,
+}
+
+pub struct Timers {
+    /// Milliseconds, wraps around
+    now: u32,
+    entries: [Option<Entry>; CAPACITY],
+    generations: [u8; CAPACITY],
+    /// Slots of the running timers, earliest deadline first
+    queue: [u8; CAPACITY],
+    len: usize,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Timers {
+            now: 0,
+            entries: [None; CAPACITY],
+            generations: [0; CAPACITY],
+            queue: [0; CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Current time in ms
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// Moves time forward, returns the new time
+    pub fn advance(&mut self, ms: u32) -> u32 {
+        self.now = self.now.wrapping_add(ms);
+        self.now
+    }
+
+    /// Calls `callback` once after `delay_ms`. `None` when all slots are taken.
+    pub fn after(&mut self, delay_ms: u32, callback: Callback) -> Option<TimerId> {
+        self.start(delay_ms, 0, callback)
+    }
+
+    /// Calls `callback` every `period_ms`, starting one period from now
+    pub fn every(&mut self, period_ms: u32, callback: Callback) -> Option<TimerId> {
+        self.start(period_ms, period_ms.max(1), callback)
+    }
+
+    /// Stops a timer. False if it had already finished or was cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        if !self.is_active(id) {
+            return false;
+        }
+        let slot = id.slot as usize;
+        self.entries[slot] = None;
+        self.generations[slot] = self.generations[slot].wrapping_add(1);
+        if let Some(position) = self.queue[..self.len]
+            .iter()
+            .position(|&s| s as usize == slot)
+        {
+            self.remove(position);
+        }
+        true
+    }
+
+    pub fn is_active(&self, id: TimerId) -> bool {
+        let slot = id.slot as usize;
+        self.entries[slot].is_some() && self.generations[slot] == id.generation
+    }
+
+    /// Time of the earliest deadline, for tickless operation
+    pub fn next_deadline(&self) -> Option<u32> {
+        if self.len == 0 {
+            None
+        } else {
+            self.entries[self.queue[0] as usize].map(|entry| entry.deadline)
+        }
+    }
+
+    /// Takes the next timer that is due. Periodic timers are queued again.
+    /// Call until it returns `None` and run each callback.
+    pub fn next_expired(&mut self) -> Option<(TimerId, Callback)> {
+        if self.len == 0 {
+            return None;
+        }
+        let slot = self.queue[0] as usize;
+        let mut entry = self.entries[slot]?;
+        if self.until(entry.deadline) > 0 {
+            return None;
+        }
+        self.remove(0);
+        let id = TimerId {
+            slot: slot as u8,
+            generation: self.generations[slot],
+        };
+
+        if entry.period == 0 {
+            self.entries[slot] = None;
+            self.generations[slot] = self.generations[slot].wrapping_add(1);
+        } else {
+            // Keep the phase, but don't try to catch up on missed periods
+            entry.deadline = entry.deadline.wrapping_add(entry.period);
+            if self.until(entry.deadline) <= 0 {
+                entry.deadline = self.now.wrapping_add(entry.period);
+            }
+            self.entries[slot] = Some(entry);
+            self.insert(slot);
+        }
+        Some((id, entry.callback))
+    }
+
+    fn start(&mut self, delay_ms: u32, period: u32, callback: Callback) -> Option<TimerId> {
+        let slot = self.entries.iter().position(Option::is_none)?;
+        self.entries[slot] = Some(Entry {
+            deadline: self.now.wrapping_add(delay_ms),
+            period,
+            callback,
+        });
+        self.insert(slot);
+        Some(TimerId {
+            slot: slot as u8,
+            generation: self.generations[slot],
+        })
+    }
+
+    /// Time left until `deadline`, negative when past
+    fn until(&self, deadline: u32) -> i32 {
+        deadline.wrapping_sub(self.now) as i32
+    }
+
+    /// Queues `slot` behind the entries due no later than it, so equal deadlines run in start order
+    fn insert(&mut self, slot: usize) {
+        let deadline = match self.entries[slot] {
+            Some(entry) => self.until(entry.deadline),
+            None => return,
+        };
+        let entries = &self.entries;
+        let now = self.now;
+        let position = self.queue[..self.len]
+            .iter()
+            .position(|&s| match entries[s as usize] {
+                Some(entry) => entry.deadline.wrapping_sub(now) as i32 > deadline,
+                None => false,
+            })
+            .unwrap_or(self.len);
+        for i in (position..self.len).rev() {
+            self.queue[i + 1] = self.queue[i];
+        }
+        self.queue[position] = slot as u8;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, position: usize) {
+        for i in position..self.len - 1 {
+            self.queue[i] = self.queue[i + 1];
+        }
+        self.len -= 1;
+    }
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Timers::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nop(_: TimerId) {}
+
+    /// Ids of the timers that are due, in the order they come out
+    fn expired(timers: &mut Timers) -> Vec<TimerId> {
+        let mut ids = Vec::new();
+        while let Some((id, _)) = timers.next_expired() {
+            ids.push(id);
+        }
+        ids
+    }
+
+    #[test]
+    fn expire_in_deadline_order() {
+        let mut timers = Timers::new();
+        let late = timers.after(30, nop).unwrap();
+        let early = timers.after(10, nop).unwrap();
+        let middle = timers.after(20, nop).unwrap();
+        assert_eq!(timers.next_deadline(), Some(10));
+        timers.advance(9);
+        assert_eq!(expired(&mut timers), []);
+        timers.advance(21);
+        assert_eq!(expired(&mut timers), [early, middle, late]);
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn equal_deadlines_run_in_start_order() {
+        let mut timers = Timers::new();
+        let ids: Vec<TimerId> = (0..5).map(|_| timers.after(10, nop).unwrap()).collect();
+        // Started later, but due at the same time as the others
+        timers.advance(5);
+        let last = timers.after(5, nop).unwrap();
+        timers.advance(5);
+        let mut expected = ids;
+        expected.push(last);
+        assert_eq!(expired(&mut timers), expected);
+    }
+
+    #[test]
+    fn stale_ids_do_not_cancel_a_reused_slot() {
+        let mut timers = Timers::new();
+        let first = timers.after(10, nop).unwrap();
+        assert!(timers.cancel(first));
+        assert!(!timers.cancel(first));
+
+        // Same slot, new generation
+        let second = timers.after(10, nop).unwrap();
+        assert_ne!(first, second);
+        assert!(!timers.is_active(first));
+        assert!(!timers.cancel(first));
+        assert!(timers.is_active(second));
+
+        // Finished one-shots go stale too
+        timers.advance(10);
+        assert_eq!(expired(&mut timers), [second]);
+        let third = timers.after(10, nop).unwrap();
+        assert!(!timers.cancel(second));
+        assert!(timers.is_active(third));
+    }
+
+    #[test]
+    fn cancelled_timers_leave_the_queue() {
+        let mut timers = Timers::new();
+        let a = timers.after(10, nop).unwrap();
+        let b = timers.after(20, nop).unwrap();
+        let c = timers.after(30, nop).unwrap();
+        assert!(timers.cancel(b));
+        timers.advance(30);
+        assert_eq!(expired(&mut timers), [a, c]);
+    }
+
+    #[test]
+    fn periodic_timers_keep_their_phase() {
+        let mut timers = Timers::new();
+        let id = timers.every(10, nop).unwrap();
+        for tick in 1..=3 {
+            timers.advance(10);
+            assert_eq!(expired(&mut timers), [id]);
+            assert_eq!(timers.next_deadline(), Some(10 * (tick + 1)));
+        }
+        assert!(timers.is_active(id));
+        assert!(timers.cancel(id));
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn periodic_timers_do_not_catch_up() {
+        let mut timers = Timers::new();
+        let id = timers.every(10, nop).unwrap();
+        // Three and a half periods late, the missed ones are dropped
+        timers.advance(35);
+        assert_eq!(expired(&mut timers), [id]);
+        assert_eq!(timers.next_deadline(), Some(45));
+        timers.advance(10);
+        assert_eq!(expired(&mut timers), [id]);
+        assert_eq!(timers.next_deadline(), Some(55));
+    }
+
+    #[test]
+    fn time_wraps_around() {
+        let mut timers = Timers::new();
+        timers.advance(u32::MAX - 4);
+        let wrapped = timers.after(10, nop).unwrap();
+        let before = timers.after(3, nop).unwrap();
+        assert_eq!(timers.next_deadline(), Some(u32::MAX - 1));
+        timers.advance(3);
+        assert_eq!(expired(&mut timers), [before]);
+        timers.advance(6);
+        assert_eq!(timers.now(), 4);
+        assert_eq!(expired(&mut timers), []);
+        timers.advance(1);
+        assert_eq!(expired(&mut timers), [wrapped]);
+
+        let periodic = timers.every(u32::MAX / 2, nop).unwrap();
+        timers.advance(u32::MAX / 2);
+        assert_eq!(expired(&mut timers), [periodic]);
+    }
+
+    #[test]
+    fn full_table() {
+        let mut timers = Timers::new();
+        let ids: Vec<TimerId> = (0..CAPACITY)
+            .map(|i| timers.after(i as u32, nop).unwrap())
+            .collect();
+        assert_eq!(timers.after(1, nop), None);
+        assert!(timers.cancel(ids[3]));
+        assert!(timers.every(1, nop).is_some());
+    }
+}
@@ -0,0 +1,205 @@
+//! PSC/ARR solver for timer periods and rates.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// Desired update period, or rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Nanos(u64),
+    Millihertz(u64),
+}
+
+impl Target {
+    pub fn from_nanos(nanos: u64) -> Self {
+        Target::Nanos(nanos)
+    }
+
+    /// Saturates, `solve` clamps anything that long to the longest period anyway
+    pub fn from_micros(micros: u64) -> Self {
+        Target::Nanos(micros.saturating_mul(1_000))
+    }
+
+    pub fn from_millis(millis: u64) -> Self {
+        Target::Nanos(millis.saturating_mul(1_000_000))
+    }
+
+    pub fn from_hz(hz: u32) -> Self {
+        Target::Millihertz(hz as u64 * 1_000)
+    }
+
+    /// Fractional rates, 2.5 Hz is 2500
+    pub fn from_millihertz(millihertz: u64) -> Self {
+        Target::Millihertz(millihertz)
+    }
+
+    /// Timer ticks per period in 1/1000 tick
+    fn milliticks(self, timer_clock: u32) -> u128 {
+        match self {
+            Target::Nanos(nanos) => timer_clock as u128 * nanos as u128 / 1_000_000,
+            Target::Millihertz(mhz) => timer_clock as u128 * 1_000_000 / mhz.max(1) as u128,
+        }
+    }
+}
+
+/// Register values and what they give
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub psc: u16,
+    pub arr: u32,
+    pub timer_clock: u32,
+    /// Achieved period against the target, positive when longer
+    pub error_ppm: i32,
+}
+
+impl Solution {
+    /// Timer ticks per period
+    pub fn ticks(&self) -> u64 {
+        (self.psc as u64 + 1) * (self.arr as u64 + 1)
+    }
+
+    pub fn achieved_millihertz(&self) -> u64 {
+        self.timer_clock as u64 * 1_000 / self.ticks()
+    }
+
+    pub fn period_nanos(&self) -> u64 {
+        (self.ticks() as u128 * 1_000_000_000 / self.timer_clock as u128) as u64
+    }
+}
+
+/// Best PSC/ARR for `target`. Among equally close pairs the smallest
+/// prescaler wins, it leaves the most ARR resolution for PWM.
+/// Out of range targets are clamped to the shortest or longest period.
+pub fn solve(timer_clock: u32, target: Target, max_arr: u32) -> Solution {
+    let max_reload = max_arr as u64 + 1;
+    let exact = target.milliticks(timer_clock).max(1);
+    // Clamped to what the timer can do, fits u64 from here on
+    let wanted = exact.min(0x1_0000 * max_reload as u128 * 1_000) as u64;
+    let first = wanted.div_ceil(max_reload * 1_000).max(1);
+    // Beyond this the reload is stuck at 1 and the period only grows
+    let last = (wanted / 1_000 + 1).min(0x1_0000);
+
+    let mut best = (u64::MAX, 1, 1);
+    for prescaler in first..=last {
+        let reload = ((wanted + prescaler * 500) / (prescaler * 1_000))
+            .max(1)
+            .min(max_reload);
+        let ticks = prescaler * reload * 1_000;
+        let error = ticks.abs_diff(wanted);
+        if error < best.0 {
+            best = (error, prescaler, reload);
+            if error == 0 {
+                break;
+            }
+        }
+    }
+
+    let (_, prescaler, reload) = best;
+    let ticks = prescaler * reload * 1_000;
+    let error_ppm = (ticks as i128 - exact as i128) * 1_000_000 / exact as i128;
+    Solution {
+        psc: (prescaler - 1) as u16,
+        arr: (reload - 1) as u32,
+        timer_clock,
+        error_ppm: error_ppm.max(i32::MIN as i128).min(i32::MAX as i128) as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK: u32 = 48_000_000;
+    const MAX_16: u32 = 0xFFFF;
+    const MAX_32: u32 = 0xFFFF_FFFF;
+
+    #[test]
+    fn exact_period() {
+        let s = solve(CLOCK, Target::from_micros(1_500), MAX_16);
+        // 72000 ticks don't fit 16 bits with PSC 0
+        assert_eq!((s.psc, s.arr), (1, 35_999));
+        assert_eq!(s.error_ppm, 0);
+        assert_eq!(s.period_nanos(), 1_500_000);
+    }
+
+    #[test]
+    fn fractional_rates() {
+        let s = solve(CLOCK, Target::from_millihertz(2_500), MAX_16);
+        assert_eq!((s.psc, s.arr), (299, 63_999));
+        assert_eq!(s.achieved_millihertz(), 2_500);
+        assert_eq!(s.error_ppm, 0);
+
+        let s = solve(CLOCK, Target::from_millihertz(100), MAX_16);
+        assert_eq!(s.ticks(), 480_000_000);
+        assert_eq!(s.achieved_millihertz(), 100);
+        assert_eq!(s.error_ppm, 0);
+    }
+
+    #[test]
+    fn wide_counter_needs_no_prescaler() {
+        let s = solve(CLOCK, Target::from_micros(1_500), MAX_32);
+        assert_eq!((s.psc, s.arr), (0, 71_999));
+
+        let s = solve(CLOCK, Target::from_millihertz(100), MAX_32);
+        assert_eq!((s.psc, s.arr), (0, 479_999_999));
+    }
+
+    #[test]
+    fn clamps_short_targets() {
+        let s = solve(CLOCK, Target::from_nanos(1), MAX_16);
+        assert_eq!((s.psc, s.arr), (0, 0));
+        // One tick is longer than asked for
+        assert!(s.error_ppm > 0);
+    }
+
+    #[test]
+    fn clamps_long_targets() {
+        let s = solve(CLOCK, Target::from_millihertz(1), MAX_16);
+        assert_eq!((s.psc, s.arr), (0xFFFF, 0xFFFF));
+        assert!(s.error_ppm < 0);
+
+        // The error stays in range at both extremes
+        let s = solve(CLOCK, Target::from_nanos(u64::MAX / 1_000), MAX_16);
+        assert!(s.error_ppm < 0 && s.error_ppm > -1_000_000);
+        let s = solve(CLOCK, Target::from_millihertz(u64::MAX), MAX_16);
+        assert_eq!((s.psc, s.arr), (0, 0));
+        assert_eq!(s.error_ppm, 999_000_000);
+    }
+
+    #[test]
+    fn long_units_saturate() {
+        assert_eq!(Target::from_micros(u64::MAX), Target::Nanos(u64::MAX));
+        assert_eq!(
+            Target::from_millis(u64::MAX / 1_000),
+            Target::Nanos(u64::MAX)
+        );
+        let s = solve(CLOCK, Target::from_millis(u64::MAX), MAX_16);
+        assert_eq!((s.psc, s.arr), (0xFFFF, 0xFFFF));
+    }
+
+    #[test]
+    fn error_sign_follows_period() {
+        // 1/7 ms is 6857.14 ticks, rounds to a shorter period
+        let target = 142_857;
+        let s = solve(CLOCK, Target::from_nanos(target), MAX_16);
+        assert_eq!(s.ticks(), 6_857);
+        assert!(s.error_ppm < 0);
+        assert!(s.period_nanos() < target);
+
+        // 6857.6 ticks rounds up to a longer period
+        let target = 142_867;
+        let s = solve(CLOCK, Target::from_nanos(target), MAX_16);
+        assert_eq!(s.ticks(), 6_858);
+        assert!(s.error_ppm > 0);
+        assert!(s.period_nanos() > target);
+    }
+
+    #[test]
+    fn smallest_prescaler_wins_ties() {
+        // 48000 ticks is also PSC 47 ARR 999 and many more
+        let s = solve(CLOCK, Target::from_millis(1), MAX_16);
+        assert_eq!((s.psc, s.arr), (0, 47_999));
+
+        let s = solve(CLOCK, Target::from_hz(1), MAX_16);
+        // 48e6 = 733 * 65484.3.., the first exact split is 750 * 64000
+        assert_eq!((s.psc, s.arr), (749, 63_999));
+    }
+}
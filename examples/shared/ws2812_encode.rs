@@ -0,0 +1,257 @@
+//! WS2812B bit encoding for `ws2812_1.rs`.
+//! No hardware access, so the tests run on the host (see `host-tests`).
+
+/// Pixel color, sent to the LED in G, R, B order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    fn grb(self) -> [u8; 3] {
+        [self.g, self.r, self.b]
+    }
+}
+
+/// SPI clock the SPI encoding is made for. Every data bit takes three SPI bits,
+/// so one WS2812 bit is 1 µs long with a 333 ns (0) or 667 ns (1) high time.
+pub const SPI_HZ: u32 = 3_000_000;
+/// SPI bytes per LED, 24 data bits times 3
+pub const SPI_BYTES_PER_LED: usize = 9;
+/// Low bytes after the data to latch. 300 µs covers the newer WS2812B parts.
+pub const SPI_RESET_BYTES: usize = 113;
+
+/// PWM period of one data bit, 800 kHz
+pub const PWM_HZ: u32 = 800_000;
+/// Zero duty periods after the data to latch, 300 µs
+pub const PWM_RESET_SLOTS: usize = 240;
+
+/// T0H and T1H in ns
+const T0H_NS: u32 = 350;
+const T1H_NS: u32 = 700;
+
+/// Buffer size for `leds` LEDs with the SPI encoding
+pub const fn spi_len(leds: usize) -> usize {
+    leds * SPI_BYTES_PER_LED + SPI_RESET_BYTES
+}
+
+/// Buffer size for `leds` LEDs with the PWM encoding
+pub const fn pwm_len(leds: usize) -> usize {
+    leds * 24 + PWM_RESET_SLOTS
+}
+
+/// One color byte as 24 SPI bits, MSB first: 0 -> 100, 1 -> 110
+pub fn spi_byte(byte: u8) -> [u8; 3] {
+    let mut bits: u32 = 0;
+    for i in (0..8).rev() {
+        let pattern = if byte & (1 << i) != 0 { 0b110 } else { 0b100 };
+        bits = bits << 3 | pattern;
+    }
+    [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]
+}
+
+/// Encodes `pixels` for SPI. LEDs that don't fit are dropped, the rest of
+/// the buffer is cleared so it ends with the reset time.
+/// Returns the number of LEDs encoded.
+pub fn spi<I>(pixels: I, buffer: &mut [u8]) -> usize
+where
+    I: IntoIterator<Item = Rgb>,
+{
+    let capacity = buffer.len().saturating_sub(SPI_RESET_BYTES) / SPI_BYTES_PER_LED;
+    let mut leds = 0;
+    for (pixel, chunk) in pixels
+        .into_iter()
+        .zip(buffer.chunks_exact_mut(SPI_BYTES_PER_LED))
+        .take(capacity)
+    {
+        for (byte, out) in pixel.grb().iter().zip(chunk.chunks_exact_mut(3)) {
+            out.copy_from_slice(&spi_byte(*byte));
+        }
+        leds += 1;
+    }
+    for byte in buffer[leds * SPI_BYTES_PER_LED..].iter_mut() {
+        *byte = 0;
+    }
+    leds
+}
+
+/// Compare values for a timer running at `timer_hz` with an 800 kHz period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwmTiming {
+    pub period: u16,
+    pub zero: u16,
+    pub one: u16,
+}
+
+impl PwmTiming {
+    pub fn new(timer_hz: u32) -> Self {
+        let ticks = |ns: u32| ((timer_hz as u64 * ns as u64 + 500_000_000) / 1_000_000_000) as u16;
+        PwmTiming {
+            period: (timer_hz / PWM_HZ) as u16,
+            zero: ticks(T0H_NS),
+            one: ticks(T1H_NS),
+        }
+    }
+}
+
+/// Encodes `pixels` as one compare value per bit. Same rules as `spi`.
+pub fn pwm<I>(pixels: I, buffer: &mut [u16], timing: PwmTiming) -> usize
+where
+    I: IntoIterator<Item = Rgb>,
+{
+    let capacity = buffer.len().saturating_sub(PWM_RESET_SLOTS) / 24;
+    let mut leds = 0;
+    for (pixel, chunk) in pixels
+        .into_iter()
+        .zip(buffer.chunks_exact_mut(24))
+        .take(capacity)
+    {
+        for (byte, out) in pixel.grb().iter().zip(chunk.chunks_exact_mut(8)) {
+            for (i, slot) in out.iter_mut().enumerate() {
+                *slot = if byte & (0x80 >> i) != 0 {
+                    timing.one
+                } else {
+                    timing.zero
+                };
+            }
+        }
+        leds += 1;
+    }
+    for slot in buffer[leds * 24..].iter_mut() {
+        *slot = 0;
+    }
+    leds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inverse of `spi_byte`, `None` if the pattern is not valid
+    fn decode_spi_byte(encoded: [u8; 3]) -> Option<u8> {
+        let bits = (encoded[0] as u32) << 16 | (encoded[1] as u32) << 8 | encoded[2] as u32;
+        let mut byte = 0;
+        for i in (0..8).rev() {
+            byte <<= 1;
+            match bits >> (i * 3) & 0b111 {
+                0b100 => {}
+                0b110 => byte |= 1,
+                _ => return None,
+            }
+        }
+        Some(byte)
+    }
+
+    fn pixels() -> Vec<Rgb> {
+        vec![
+            Rgb::new(0xFF, 0x00, 0x80),
+            Rgb::new(0x01, 0x02, 0x03),
+            Rgb::new(0x5A, 0xA5, 0x00),
+        ]
+    }
+
+    #[test]
+    fn spi_bytes_round_trip() {
+        for byte in 0..=255u8 {
+            assert_eq!(decode_spi_byte(spi_byte(byte)), Some(byte));
+        }
+        assert_eq!(spi_byte(0x00), [0b1001_0010, 0b0100_1001, 0b0010_0100]);
+        assert_eq!(spi_byte(0xFF), [0b1101_1011, 0b0110_1101, 0b1011_0110]);
+    }
+
+    #[test]
+    fn invalid_spi_patterns_are_rejected() {
+        assert_eq!(decode_spi_byte([0, 0, 0]), None);
+        assert_eq!(decode_spi_byte([0xFF, 0xFF, 0xFF]), None);
+        // One 111 slot in an otherwise valid byte
+        let mut encoded = spi_byte(0x00);
+        encoded[2] |= 0b011;
+        assert_eq!(decode_spi_byte(encoded), None);
+    }
+
+    #[test]
+    fn spi_frame_round_trips_in_grb_order() {
+        let mut buffer = [0xEE; spi_len(3)];
+        assert_eq!(spi(pixels(), &mut buffer), 3);
+        let decoded: Vec<Rgb> = buffer[..3 * SPI_BYTES_PER_LED]
+            .chunks(SPI_BYTES_PER_LED)
+            .map(|led| {
+                let byte = |i: usize| decode_spi_byte([led[i], led[i + 1], led[i + 2]]).unwrap();
+                Rgb::new(byte(3), byte(0), byte(6))
+            })
+            .collect();
+        assert_eq!(decoded, pixels());
+        // The line stays low for the reset time
+        assert!(buffer[3 * SPI_BYTES_PER_LED..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn spi_drops_leds_that_do_not_fit() {
+        let mut buffer = [0xEE; spi_len(2)];
+        assert_eq!(spi(pixels(), &mut buffer), 2);
+        assert!(buffer[2 * SPI_BYTES_PER_LED..].iter().all(|&b| b == 0));
+        // Fewer pixels than room clears the rest, including stale LEDs
+        assert_eq!(spi(pixels().into_iter().take(1), &mut buffer), 1);
+        assert!(buffer[SPI_BYTES_PER_LED..].iter().all(|&b| b == 0));
+        // Too small for the reset time, nothing is sent
+        assert_eq!(spi(pixels(), &mut [0u8; SPI_RESET_BYTES]), 0);
+    }
+
+    #[test]
+    fn spi_bit_time() {
+        // Three SPI bits per data bit, 1 µs per WS2812 bit
+        assert_eq!(SPI_HZ / 3, 1_000_000);
+        assert_eq!(spi_len(300), 300 * 9 + 113);
+        // The reset bytes last at least 300 µs
+        assert!(SPI_RESET_BYTES as u32 * 8 * 1_000_000 / SPI_HZ >= 300);
+    }
+
+    #[test]
+    fn pwm_timing_at_96_mhz() {
+        let timing = PwmTiming::new(96_000_000);
+        assert_eq!(
+            timing,
+            PwmTiming {
+                period: 120,
+                zero: 34,
+                one: 67,
+            }
+        );
+    }
+
+    #[test]
+    fn pwm_frame_round_trips_in_grb_order() {
+        let timing = PwmTiming::new(48_000_000);
+        let mut buffer = [0xEEEE; pwm_len(3)];
+        assert_eq!(pwm(pixels(), &mut buffer, timing), 3);
+        let decoded: Vec<Rgb> = buffer[..3 * 24]
+            .chunks(24)
+            .map(|led| {
+                let byte = |i: usize| {
+                    led[i * 8..i * 8 + 8].iter().fold(0u8, |byte, &slot| {
+                        assert!(slot == timing.zero || slot == timing.one);
+                        byte << 1 | (slot == timing.one) as u8
+                    })
+                };
+                Rgb::new(byte(1), byte(0), byte(2))
+            })
+            .collect();
+        assert_eq!(decoded, pixels());
+        assert!(buffer[3 * 24..].iter().all(|&slot| slot == 0));
+        assert_eq!(buffer.len() - 3 * 24, PWM_RESET_SLOTS);
+    }
+
+    #[test]
+    fn pwm_drops_leds_that_do_not_fit() {
+        let timing = PwmTiming::new(48_000_000);
+        let mut buffer = [0xEEEE; pwm_len(1)];
+        assert_eq!(pwm(pixels(), &mut buffer, timing), 1);
+        assert!(buffer[24..].iter().all(|&slot| slot == 0));
+    }
+}
@@ -0,0 +1,95 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use clock::{Duration, Instant};
+use core::cell::{Cell, RefCell};
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::{iprintln, peripheral};
+use cortex_m_rt::entry;
+use stm32f4xx_hal::{
+    gpio::gpioc::PC13,
+    gpio::{Edge, ExtiPin, Input, PullDown},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+};
+
+#[allow(dead_code)]
+#[path = "shared/clock.rs"]
+mod clock;
+
+// Global resources
+static BUTTON: Mutex<RefCell<Option<PC13<Input<PullDown>>>>> = Mutex::new(RefCell::new(None));
+static PRESSED_AT: Mutex<Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    // Enable SYSCFG clock for the EXTI line mapping
+    dp.RCC.apb2enr.write(|w| w.syscfgen().enabled());
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // Start the clock
+    clock::init(dp.TIM2, dp.TIM5, clocks);
+
+    // Set up the user button
+    let gpioc = dp.GPIOC.split();
+    let mut user_button = gpioc.pc13.into_pull_down_input();
+    user_button.make_interrupt_source(&mut dp.SYSCFG);
+    user_button.enable_interrupt(&mut dp.EXTI);
+    user_button.trigger_on_edge(&mut dp.EXTI, Edge::RISING);
+
+    free(|cs| {
+        BUTTON.borrow(cs).replace(Some(user_button));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::EXTI15_10);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::EXTI15_10);
+    }
+
+    // Once a second on an absolute schedule, so the delays don't add up
+    let period = Duration::from_secs(1);
+    let mut next = clock::now() + period;
+    loop {
+        while clock::now() < next {}
+        let now = clock::now();
+        let uptime = now - Instant::from_micros(0);
+        iprintln!(
+            itm(),
+            "up {}.{:06} s, late by {} us",
+            uptime.as_secs(),
+            uptime.subsec_micros(),
+            (now - next).as_micros()
+        );
+        if let Some(pressed) = free(|cs| PRESSED_AT.borrow(cs).get()) {
+            iprintln!(
+                itm(),
+                "button pressed {} ms ago",
+                pressed.elapsed().as_millis()
+            );
+        }
+        next += period;
+    }
+}
+
+// Timestamps the button press
+#[interrupt]
+fn EXTI15_10() {
+    let now = clock::now();
+    free(|cs| {
+        if let Some(ref mut btn) = BUTTON.borrow(cs).borrow_mut().deref_mut() {
+            btn.clear_interrupt_pending_bit();
+        }
+        PRESSED_AT.borrow(cs).set(Some(now));
+    });
+}
@@ -0,0 +1,225 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::{iprintln, peripheral};
+use cortex_m_rt::entry;
+use periodic::PeriodicTimer;
+use stm32f4xx_hal::{
+    gpio::gpiob::{PB0, PB14, PB7},
+    gpio::{Output, PushPull},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+};
+use timing::{Solution, Target};
+
+struct Blinkers {
+    tim2: PeriodicTimer<stm32::TIM2>,
+    tim3: PeriodicTimer<stm32::TIM3>,
+    tim6: PeriodicTimer<stm32::TIM6>,
+    green: PB0<Output<PushPull>>,
+    blue: PB7<Output<PushPull>>,
+    red: PB14<Output<PushPull>>,
+}
+
+static BLINKERS: Mutex<RefCell<Option<Blinkers>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+fn report(name: &str, solution: &Solution) {
+    iprintln!(
+        itm(),
+        "{}: PSC {} ARR {} -> {}.{:03} Hz, {} ns, error {} ppm",
+        name,
+        solution.psc,
+        solution.arr,
+        solution.achieved_millihertz() / 1_000,
+        solution.achieved_millihertz() % 1_000,
+        solution.period_nanos(),
+        solution.error_ppm
+    );
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // Set up LEDs
+    let gpiob = dp.GPIOB.split();
+    let green = gpiob.pb0.into_push_pull_output();
+    let blue = gpiob.pb7.into_push_pull_output();
+    let red = gpiob.pb14.into_push_pull_output();
+
+    // A period, a fractional rate and a rate far below 1 Hz
+    let mut tim2 = PeriodicTimer::tim2(dp.TIM2, Target::from_micros(1_500), clocks);
+    let mut tim3 = PeriodicTimer::tim3(dp.TIM3, Target::from_millihertz(2_500), clocks);
+    let mut tim6 = PeriodicTimer::tim6(dp.TIM6, Target::from_millihertz(100), clocks);
+    report("TIM2 1.5 ms", tim2.solution());
+    report("TIM3 2.5 Hz", tim3.solution());
+    report("TIM6 0.1 Hz", tim6.solution());
+
+    // Odd targets need the solver, no PSC/ARR pair hits 1/7 ms exactly
+    let odd = timing::solve(48_000_000, Target::from_nanos(142_857), 0xFFFF);
+    report("1/7 ms", &odd);
+
+    tim2.listen();
+    tim3.listen();
+    tim6.listen();
+
+    free(|cs| {
+        BLINKERS.borrow(cs).replace(Some(Blinkers {
+            tim2,
+            tim3,
+            tim6,
+            green,
+            blue,
+            red,
+        }));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    stm32::NVIC::unpend(stm32::Interrupt::TIM3);
+    stm32::NVIC::unpend(stm32::Interrupt::TIM6_DAC);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+        stm32::NVIC::unmask(stm32::Interrupt::TIM3);
+        stm32::NVIC::unmask(stm32::Interrupt::TIM6_DAC);
+    }
+
+    loop {}
+}
+
+// 1.5 ms, PB7 shows a 333.3 Hz square wave
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut b) = BLINKERS.borrow(cs).borrow_mut().deref_mut() {
+            b.tim2.clear_interrupt();
+            b.blue.toggle().unwrap();
+        }
+    });
+}
+
+#[interrupt]
+fn TIM3() {
+    free(|cs| {
+        if let Some(ref mut b) = BLINKERS.borrow(cs).borrow_mut().deref_mut() {
+            b.tim3.clear_interrupt();
+            b.green.toggle().unwrap();
+        }
+    });
+}
+
+#[interrupt]
+fn TIM6_DAC() {
+    free(|cs| {
+        if let Some(ref mut b) = BLINKERS.borrow(cs).borrow_mut().deref_mut() {
+            b.tim6.clear_interrupt();
+            b.red.toggle().unwrap();
+        }
+    });
+}
+
+#[allow(dead_code)]
+#[path = "shared/timing.rs"]
+mod timing;
+
+// Every TIMx is generated, the example only runs three of them
+#[allow(dead_code)]
+mod periodic {
+    use crate::timing::{solve, Solution, Target};
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{
+        RCC, TIM1, TIM10, TIM11, TIM12, TIM13, TIM14, TIM2, TIM3, TIM4, TIM5, TIM6, TIM7, TIM8,
+        TIM9,
+    };
+
+    /// Free running timer with an update event every period
+    pub struct PeriodicTimer<TIM> {
+        tim: TIM,
+        solution: Solution,
+    }
+
+    macro_rules! periodic {
+        ($($TIM:ident: ($tim:ident, $enr:ident, $timen:ident, $pclk:ident, $ppre:ident, $max:expr),)+) => {
+            $(
+                impl PeriodicTimer<$TIM> {
+                    /// Configures and starts the timer
+                    pub fn $tim(tim: $TIM, target: Target, clocks: Clocks) -> Self {
+                        let rcc = unsafe { &(*RCC::ptr()) };
+                        rcc.$enr.modify(|_, w| w.$timen().set_bit());
+
+                        let timer_clock = if clocks.$ppre() == 1 {
+                            clocks.$pclk().0
+                        } else {
+                            clocks.$pclk().0 * 2
+                        };
+                        let solution = solve(timer_clock, target, $max);
+                        tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        tim.psc.write(|w| w.psc().bits(solution.psc));
+                        tim.arr.write(|w| unsafe { w.bits(solution.arr) });
+                        // Load PSC now, without a spurious interrupt
+                        tim.cr1.modify(|_, w| w.urs().set_bit());
+                        tim.egr.write(|w| w.ug().set_bit());
+                        tim.cr1.modify(|_, w| w.urs().clear_bit().cen().set_bit());
+                        PeriodicTimer { tim, solution }
+                    }
+
+                    pub fn solution(&self) -> &Solution {
+                        &self.solution
+                    }
+
+                    pub fn listen(&mut self) {
+                        self.tim.dier.modify(|_, w| w.uie().set_bit());
+                    }
+
+                    pub fn unlisten(&mut self) {
+                        self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                    }
+
+                    pub fn clear_interrupt(&mut self) {
+                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    }
+
+                    /// Blocks until the end of the current period
+                    pub fn wait(&mut self) {
+                        while self.tim.sr.read().uif().bit_is_clear() {}
+                        self.clear_interrupt();
+                    }
+
+                    pub fn release(self) -> $TIM {
+                        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                        self.tim
+                    }
+                }
+            )+
+        };
+    }
+
+    periodic! {
+        TIM1: (tim1, apb2enr, tim1en, pclk2, ppre2, 0xFFFF),
+        TIM2: (tim2, apb1enr, tim2en, pclk1, ppre1, 0xFFFF_FFFF),
+        TIM3: (tim3, apb1enr, tim3en, pclk1, ppre1, 0xFFFF),
+        TIM4: (tim4, apb1enr, tim4en, pclk1, ppre1, 0xFFFF),
+        TIM5: (tim5, apb1enr, tim5en, pclk1, ppre1, 0xFFFF_FFFF),
+        TIM6: (tim6, apb1enr, tim6en, pclk1, ppre1, 0xFFFF),
+        TIM7: (tim7, apb1enr, tim7en, pclk1, ppre1, 0xFFFF),
+        TIM8: (tim8, apb2enr, tim8en, pclk2, ppre2, 0xFFFF),
+        TIM9: (tim9, apb2enr, tim9en, pclk2, ppre2, 0xFFFF),
+        TIM10: (tim10, apb2enr, tim10en, pclk2, ppre2, 0xFFFF),
+        TIM11: (tim11, apb2enr, tim11en, pclk2, ppre2, 0xFFFF),
+        TIM12: (tim12, apb1enr, tim12en, pclk1, ppre1, 0xFFFF),
+        TIM13: (tim13, apb1enr, tim13en, pclk1, ppre1, 0xFFFF),
+        TIM14: (tim14, apb1enr, tim14en, pclk1, ppre1, 0xFFFF),
+    }
+}
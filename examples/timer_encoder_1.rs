@@ -0,0 +1,283 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::{iprintln, peripheral};
+use cortex_m_rt::entry;
+use encoder::{Direction, Encoder};
+use stm32f4xx_hal::{
+    gpio::gpioa::PA3,
+    gpio::{Edge, ExtiPin, Input, PullDown},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+
+/// Velocity sample rate
+const SAMPLE_HZ: u32 = 100;
+/// Counts per revolution of the encoder, 4 x lines in x4 mode
+const CPR: u32 = 4 * 600;
+
+static ENCODER: Mutex<RefCell<Option<Encoder<stm32::TIM3>>>> = Mutex::new(RefCell::new(None));
+static INDEX: Mutex<RefCell<Option<PA3<Input<PullDown>>>>> = Mutex::new(RefCell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[entry]
+fn main() -> ! {
+    let mut dp = stm32::Peripherals::take().unwrap();
+    // Enable SYSCFG clock for the EXTI line mapping
+    dp.RCC.apb2enr.write(|w| w.syscfgen().enabled());
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // A/B on PA6/PA7 (TIM3 CH1/CH2), index (Z) on PA3
+    let gpioa = dp.GPIOA.split();
+    let pa6 = gpioa.pa6.into_alternate_af2();
+    let pa7 = gpioa.pa7.into_alternate_af2();
+    let encoder = Encoder::tim3(dp.TIM3, (pa6, pa7), 0b0011, SAMPLE_HZ);
+
+    let mut index = gpioa.pa3.into_pull_down_input();
+    index.make_interrupt_source(&mut dp.SYSCFG);
+    index.enable_interrupt(&mut dp.EXTI);
+    index.trigger_on_edge(&mut dp.EXTI, Edge::RISING);
+
+    // Set up the sampling timer
+    let mut timer = Timer::tim2(dp.TIM2, SAMPLE_HZ.hz(), clocks);
+    timer.listen(Event::TimeOut);
+
+    free(|cs| {
+        ENCODER.borrow(cs).replace(Some(encoder));
+        INDEX.borrow(cs).replace(Some(index));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    stm32::NVIC::unpend(stm32::Interrupt::EXTI3);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+        stm32::NVIC::unmask(stm32::Interrupt::EXTI3);
+    }
+
+    loop {
+        let state = free(|cs| {
+            ENCODER.borrow(cs).borrow().as_ref().map(|encoder| {
+                (
+                    encoder.position(),
+                    encoder.direction(),
+                    encoder.velocity(),
+                    encoder.is_indexed(),
+                )
+            })
+        });
+        if let Some((position, direction, velocity, indexed)) = state {
+            let arrow = match direction {
+                Direction::Up => "+",
+                Direction::Down => "-",
+            };
+            iprintln!(
+                itm(),
+                "pos {} ({}) {} {} counts/s {} rpm",
+                position,
+                if indexed { "indexed" } else { "relative" },
+                arrow,
+                velocity,
+                velocity as i64 * 60 / CPR as i64
+            );
+        }
+        cortex_m::asm::delay(48_000_000 / 4);
+    }
+}
+
+// Extends the counter and samples the velocity
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(Event::TimeOut);
+        }
+        if let Some(ref mut encoder) = ENCODER.borrow(cs).borrow_mut().deref_mut() {
+            encoder.sample();
+        }
+    });
+}
+
+// Index pulse, once per revolution
+#[interrupt]
+fn EXTI3() {
+    free(|cs| {
+        if let Some(ref mut index) = INDEX.borrow(cs).borrow_mut().deref_mut() {
+            index.clear_interrupt_pending_bit();
+        }
+        if let Some(ref mut encoder) = ENCODER.borrow(cs).borrow_mut().deref_mut() {
+            encoder.on_index();
+        }
+    });
+}
+
+#[allow(dead_code)]
+mod encoder {
+    use stm32f4xx_hal::gpio::{
+        gpioa::{PA6, PA7},
+        gpiob::{PB6, PB7},
+        Alternate, AF2,
+    };
+    use stm32f4xx_hal::stm32::{RCC, TIM3, TIM4};
+
+    /// Velocity averaging window in samples
+    const WINDOW: usize = 8;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Direction {
+        Up,
+        Down,
+    }
+
+    /// A/B channel pins of a timer
+    pub trait Pins<TIM> {}
+
+    impl Pins<TIM3> for (PA6<Alternate<AF2>>, PA7<Alternate<AF2>>) {}
+    impl Pins<TIM4> for (PB6<Alternate<AF2>>, PB7<Alternate<AF2>>) {}
+
+    /// Quadrature encoder on a 16 bit timer in x4 encoder mode.
+    ///
+    /// The hardware counter is extended to 32 bits in `sample`, which must run
+    /// at least once per 32768 counts.
+    pub struct Encoder<TIM> {
+        tim: TIM,
+        sample_hz: u32,
+        /// Hardware count when last folded into `count`
+        last: u16,
+        /// Extended count, wraps at 32 bits
+        count: i32,
+        /// Count at the first index pulse
+        index: Option<i32>,
+        /// Counts since the last velocity sample
+        pending: i32,
+        deltas: [i32; WINDOW],
+        next: usize,
+    }
+
+    macro_rules! encoder {
+        ($TIM:ident, $tim:ident, $timen:ident) => {
+            impl Encoder<$TIM> {
+                /// `filter` is the IC1F/IC2F input filter setting, 0 to 15.
+                /// `sample` is called at `sample_hz`.
+                pub fn $tim<PINS>(tim: $TIM, _pins: PINS, filter: u8, sample_hz: u32) -> Self
+                where
+                    PINS: Pins<$TIM>,
+                {
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    rcc.apb1enr.modify(|_, w| w.$timen().set_bit());
+
+                    // TI1 and TI2 as inputs, filtered
+                    tim.ccmr1_input().write(|w| unsafe {
+                        w.cc1s()
+                            .bits(0b01)
+                            .cc2s()
+                            .bits(0b01)
+                            .ic1f()
+                            .bits(filter & 0xF)
+                            .ic2f()
+                            .bits(filter & 0xF)
+                    });
+                    // Encoder mode 3, counts on every edge of both channels
+                    tim.smcr.write(|w| unsafe { w.sms().bits(0b011) });
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    tim.cnt.reset();
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                    Encoder {
+                        tim,
+                        sample_hz,
+                        last: 0,
+                        count: 0,
+                        index: None,
+                        pending: 0,
+                        deltas: [0; WINDOW],
+                        next: 0,
+                    }
+                }
+
+                /// Swaps the counting direction
+                pub fn invert(&mut self, invert: bool) {
+                    self.tim.ccer.modify(|_, w| w.cc1p().bit(invert));
+                }
+
+                /// Extends the count and records a velocity sample.
+                /// Call at the `sample_hz` given to the constructor.
+                pub fn sample(&mut self) {
+                    self.extend();
+                    self.deltas[self.next] = self.pending;
+                    self.pending = 0;
+                    self.next = (self.next + 1) % WINDOW;
+                }
+
+                /// Latches the count at the first index pulse as the zero position.
+                /// Later pulses are ignored so the position stays multi-turn.
+                pub fn on_index(&mut self) {
+                    if self.index.is_none() {
+                        self.extend();
+                        self.index = Some(self.count);
+                    }
+                }
+
+                /// Counts from the index position, or from start before the first index pulse
+                pub fn position(&self) -> i32 {
+                    let hw = self.tim.cnt.read().bits() as u16;
+                    let count = self
+                        .count
+                        .wrapping_add(hw.wrapping_sub(self.last) as i16 as i32);
+                    count.wrapping_sub(self.index.unwrap_or(0))
+                }
+
+                /// Direction of the last count
+                pub fn direction(&self) -> Direction {
+                    if self.tim.cr1.read().dir().bit_is_set() {
+                        Direction::Down
+                    } else {
+                        Direction::Up
+                    }
+                }
+
+                /// Counts per second, averaged over the last `WINDOW` samples
+                pub fn velocity(&self) -> i32 {
+                    let sum: i64 = self.deltas.iter().map(|&delta| delta as i64).sum();
+                    // In i64, fast encoders at high sample rates overflow i32 before the division
+                    let velocity = sum * self.sample_hz as i64 / WINDOW as i64;
+                    velocity.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+                }
+
+                /// True once an index pulse was seen
+                pub fn is_indexed(&self) -> bool {
+                    self.index.is_some()
+                }
+
+                pub fn release(self) -> $TIM {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+
+                /// Folds the hardware count into the extended count
+                fn extend(&mut self) {
+                    let hw = self.tim.cnt.read().bits() as u16;
+                    let delta = hw.wrapping_sub(self.last) as i16 as i32;
+                    self.last = hw;
+                    self.count = self.count.wrapping_add(delta);
+                    self.pending += delta;
+                }
+            }
+        };
+    }
+
+    encoder!(TIM3, tim3, tim3en);
+    encoder!(TIM4, tim4, tim4en);
+}
@@ -0,0 +1,240 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use cortex_m::{iprintln, peripheral};
+use cortex_m_rt::entry;
+use frequency::FrequencyCounter;
+use ranging::Method;
+use stm32f4xx_hal::{prelude::*, pwm, stm32};
+
+#[allow(dead_code)]
+#[path = "shared/frequency_range.rs"]
+mod ranging;
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    // The gate time of the counting method is timed with CYCCNT
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+
+    // Test signal on PA8 (TIM1 CH1), wire it to PA0 and PA6
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let mut signal = pwm::tim1(dp.TIM1, pa8, clocks, 1_234.hz());
+    signal.set_duty(signal.get_max_duty() / 4);
+    signal.enable();
+
+    // 32 bit counter on PA0 (TIM2 CH1) and 16 bit counter on PA6 (TIM3 CH1)
+    let pa0 = gpioa.pa0.into_alternate_af1();
+    let mut counter32 = FrequencyCounter::tim2(dp.TIM2, pa0, clocks);
+    let pa6 = gpioa.pa6.into_alternate_af2();
+    let mut counter16 = FrequencyCounter::tim3(dp.TIM3, pa6, clocks);
+
+    loop {
+        for (name, result) in [("TIM2", counter32.measure()), ("TIM3", counter16.measure())].iter()
+        {
+            match result {
+                Some(m) => {
+                    let method = match m.method {
+                        Method::Gated => "gated",
+                        Method::Capture => "capture",
+                    };
+                    iprintln!(
+                        itm(),
+                        "{}: {}.{:03} Hz ({}, PSC {})",
+                        name,
+                        m.millihertz / 1_000,
+                        m.millihertz % 1_000,
+                        method,
+                        m.psc
+                    );
+                    if let Some(duty) = m.duty_permille {
+                        iprintln!(itm(), "      duty {}.{}%", duty / 10, duty % 10);
+                    }
+                }
+                None => iprintln!(itm(), "{}: no signal", name),
+            }
+        }
+        cortex_m::asm::delay(48_000_000);
+    }
+}
+
+mod frequency {
+    use crate::ranging::{self, Capture, Measurement, CAPTURE_URS, GATE_MS};
+    use cortex_m::peripheral::DWT;
+    use stm32f4xx_hal::gpio::{
+        gpioa::{PA0, PA15, PA5, PA6},
+        gpiob::PB4,
+        gpioc::PC6,
+        Alternate, AF1, AF2,
+    };
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{RCC, TIM2, TIM3};
+
+    /// Longest wait for an edge, keeps the cycle count below the CYCCNT wrap
+    const TIMEOUT_MS: u32 = 10_000;
+
+    /// Channel 1 pins
+    pub trait Pin<TIM> {}
+
+    impl Pin<TIM2> for PA0<Alternate<AF1>> {}
+    impl Pin<TIM2> for PA5<Alternate<AF1>> {}
+    impl Pin<TIM2> for PA15<Alternate<AF1>> {}
+    impl Pin<TIM3> for PA6<Alternate<AF2>> {}
+    impl Pin<TIM3> for PB4<Alternate<AF2>> {}
+    impl Pin<TIM3> for PC6<Alternate<AF2>> {}
+
+    /// Frequency counter on channel 1 of a general purpose timer.
+    ///
+    /// High frequencies are counted over a gate time timed by the DWT cycle counter,
+    /// which has to be running. Lower ones are measured from the period with the
+    /// timer in PWM input mode, the prescaler is picked to fit the period.
+    pub struct FrequencyCounter<TIM> {
+        tim: TIM,
+        timer_clock: u32,
+        sysclk: u32,
+        /// Prescaler of the last capture, where the next one starts
+        psc: u32,
+    }
+
+    macro_rules! frequency {
+        ($TIM:ident, $tim:ident, $timen:ident, $max:expr) => {
+            impl FrequencyCounter<$TIM> {
+                pub fn $tim<PIN>(tim: $TIM, _pin: PIN, clocks: Clocks) -> Self
+                where
+                    PIN: Pin<$TIM>,
+                {
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    rcc.apb1enr.modify(|_, w| w.$timen().set_bit());
+
+                    let timer_clock = if clocks.ppre1() == 1 {
+                        clocks.pclk1().0
+                    } else {
+                        clocks.pclk1().0 * 2
+                    };
+                    tim.arr.write(|w| unsafe { w.bits($max) });
+                    FrequencyCounter {
+                        tim,
+                        timer_clock,
+                        sysclk: clocks.sysclk().0,
+                        psc: 0,
+                    }
+                }
+
+                /// Picks the method and range, `None` without a signal
+                pub fn measure(&mut self) -> Option<Measurement> {
+                    // A 16 bit counter wraps at high frequencies, shorten the gate then
+                    let mut gate = GATE_MS;
+                    let edges = loop {
+                        match self.count_edges(gate) {
+                            Some(edges) => break edges,
+                            None if gate > 1 => gate /= 10,
+                            None => return None,
+                        }
+                    };
+                    if ranging::use_gated(gate, edges) {
+                        return Some(ranging::gated(edges, gate));
+                    }
+                    loop {
+                        let capture = self.capture(self.psc)?;
+                        match ranging::range(self.psc, capture, $max) {
+                            Err(psc) => self.psc = psc,
+                            Ok(()) => {
+                                return ranging::captured(self.timer_clock, self.psc, capture)
+                            }
+                        }
+                    }
+                }
+
+                /// Counts rising edges for `gate_ms`, scaled to the exact gate time.
+                /// `None` if the counter wrapped.
+                pub fn count_edges(&mut self, gate_ms: u32) -> Option<u32> {
+                    let tim = &self.tim;
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    tim.ccer.reset();
+                    tim.ccmr1_input().write(|w| unsafe { w.cc1s().bits(0b01) });
+                    // External clock mode 1 from TI1FP1
+                    tim.smcr
+                        .write(|w| unsafe { w.ts().bits(0b101).sms().bits(0b111) });
+                    tim.psc.write(|w| w.psc().bits(0));
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.cnt.reset();
+                    tim.sr.reset();
+
+                    let gate = self.sysclk / 1_000 * gate_ms;
+                    let start = DWT::get_cycle_count();
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                    while DWT::get_cycle_count().wrapping_sub(start) < gate {}
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    let elapsed = DWT::get_cycle_count().wrapping_sub(start);
+
+                    if tim.sr.read().uif().bit_is_set() {
+                        return None;
+                    }
+                    let edges = tim.cnt.read().bits() as u64;
+                    Some((edges * gate as u64 / elapsed as u64) as u32)
+                }
+
+                /// Captures one period in PWM input mode with prescaler `psc`, `None` on timeout
+                fn capture(&mut self, psc: u32) -> Option<Capture> {
+                    let tim = &self.tim;
+                    // Only overflows may set UIF, not the reset on every edge
+                    tim.cr1
+                        .modify(|_, w| w.cen().clear_bit().urs().bit(CAPTURE_URS));
+                    tim.ccer.reset();
+                    // IC1 on TI1 rising (period), IC2 on TI1 falling (high time)
+                    tim.ccmr1_input()
+                        .write(|w| unsafe { w.cc1s().bits(0b01).cc2s().bits(0b10) });
+                    tim.ccer
+                        .write(|w| w.cc1e().set_bit().cc2p().set_bit().cc2e().set_bit());
+                    // Reset mode, every rising edge restarts the counter
+                    tim.smcr
+                        .write(|w| unsafe { w.ts().bits(0b101).sms().bits(0b100) });
+                    tim.psc.write(|w| w.psc().bits(psc as u16));
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.sr.reset();
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    let timeout = self.sysclk / 1_000 * TIMEOUT_MS;
+                    let start = DWT::get_cycle_count();
+                    let wait = || {
+                        while tim.sr.read().cc1if().bit_is_clear() {
+                            if DWT::get_cycle_count().wrapping_sub(start) > timeout {
+                                return false;
+                            }
+                        }
+                        true
+                    };
+                    // The first edge only starts the period
+                    if !wait() {
+                        return None;
+                    }
+                    tim.sr.reset();
+                    if !wait() {
+                        return None;
+                    }
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    Some(Capture::new(
+                        tim.sr.read().uif().bit_is_set(),
+                        tim.ccr1.read().bits(),
+                        tim.ccr2.read().bits(),
+                    ))
+                }
+            }
+        };
+    }
+
+    frequency!(TIM2, tim2, tim2en, 0xFFFF_FFFF);
+    frequency!(TIM3, tim3, tim3en, 0xFFFF);
+}
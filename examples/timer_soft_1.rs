@@ -0,0 +1,145 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::{Cell, RefCell};
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m_rt::entry;
+use soft::{TimerId, Timers};
+use stm32f4xx_hal::{
+    gpio::gpiob::{PB0, PB14, PB7},
+    gpio::{Output, PushPull},
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+
+#[allow(dead_code)]
+#[path = "shared/soft.rs"]
+mod soft;
+
+/// Hardware tick, the resolution of the software timers
+const TICK_HZ: u32 = 1_000;
+
+struct Leds {
+    green: PB0<Output<PushPull>>,
+    blue: PB7<Output<PushPull>>,
+    red: PB14<Output<PushPull>>,
+}
+
+static TIMERS: Mutex<RefCell<Option<Timers>>> = Mutex::new(RefCell::new(None));
+static LEDS: Mutex<RefCell<Option<Leds>>> = Mutex::new(RefCell::new(None));
+static RED_TIMER: Mutex<Cell<Option<TimerId>>> = Mutex::new(Cell::new(None));
+static TIMER_TIM2: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+fn TIM2() {
+    free(|cs| {
+        if let Some(ref mut tim2) = TIMER_TIM2.borrow(cs).borrow_mut().deref_mut() {
+            tim2.clear_interrupt(Event::TimeOut);
+        }
+        if let Some(ref mut timers) = TIMERS.borrow(cs).borrow_mut().deref_mut() {
+            timers.advance(1_000 / TICK_HZ);
+        }
+    });
+    // Callbacks run outside the borrow, so they can start and cancel timers
+    while let Some((id, callback)) = free(|cs| {
+        TIMERS
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .and_then(|timers| timers.next_expired())
+    }) {
+        callback(id);
+    }
+}
+
+fn blink_blue(_: TimerId) {
+    free(|cs| {
+        if let Some(ref mut leds) = LEDS.borrow(cs).borrow_mut().deref_mut() {
+            leds.blue.toggle().unwrap();
+        }
+    });
+}
+
+fn blink_red(_: TimerId) {
+    free(|cs| {
+        if let Some(ref mut leds) = LEDS.borrow(cs).borrow_mut().deref_mut() {
+            leds.red.toggle().unwrap();
+        }
+    });
+}
+
+// One-shot that re-arms itself with a growing delay
+fn flash_green(_: TimerId) {
+    static DELAY_MS: Mutex<Cell<u32>> = Mutex::new(Cell::new(50));
+    free(|cs| {
+        if let Some(ref mut leds) = LEDS.borrow(cs).borrow_mut().deref_mut() {
+            leds.green.toggle().unwrap();
+        }
+        let delay = DELAY_MS.borrow(cs);
+        delay.set(if delay.get() >= 800 {
+            50
+        } else {
+            delay.get() * 2
+        });
+        if let Some(ref mut timers) = TIMERS.borrow(cs).borrow_mut().deref_mut() {
+            timers.after(delay.get(), flash_green);
+        }
+    });
+}
+
+// Stops the red LED after a while and hands over to the green one
+fn handover(_: TimerId) {
+    free(|cs| {
+        if let Some(ref mut timers) = TIMERS.borrow(cs).borrow_mut().deref_mut() {
+            if let Some(red) = RED_TIMER.borrow(cs).take() {
+                timers.cancel(red);
+            }
+            timers.after(0, flash_green);
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+    // Set up LEDs
+    let gpiob = dp.GPIOB.split();
+    let leds = Leds {
+        green: gpiob.pb0.into_push_pull_output(),
+        blue: gpiob.pb7.into_push_pull_output(),
+        red: gpiob.pb14.into_push_pull_output(),
+    };
+
+    // Many software timers on one hardware timer
+    let mut timers = Timers::new();
+    timers.every(500, blink_blue).unwrap();
+    let red = timers.every(333, blink_red).unwrap();
+    timers.after(5_000, handover).unwrap();
+
+    // Set up the tick
+    let mut timer = Timer::tim2(dp.TIM2, TICK_HZ.hz(), clocks);
+    timer.listen(Event::TimeOut);
+
+    free(|cs| {
+        LEDS.borrow(cs).replace(Some(leds));
+        TIMERS.borrow(cs).replace(Some(timers));
+        RED_TIMER.borrow(cs).set(Some(red));
+        TIMER_TIM2.borrow(cs).replace(Some(timer));
+    });
+
+    // Enable interrupt
+    stm32::NVIC::unpend(stm32::Interrupt::TIM2);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM2);
+    }
+
+    loop {}
+}
@@ -0,0 +1,559 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use cortex_m::interrupt::{free, Mutex};
+use cortex_m::{iprintln, peripheral};
+use cortex_m_rt::entry;
+use maxsonar::{MaxSonar, Model};
+use stm32f4xx_hal::{
+    gpio::gpiob::PB0,
+    gpio::{Output, PushPull},
+    hal::watchdog::Watchdog,
+    prelude::*,
+    stm32,
+    stm32::interrupt,
+    timer::{Event, Timer},
+};
+use watchdog::{Iwdg, ResetCause, TaskId, TaskWatchdog, Wwdg};
+
+/// Every task has to check in within this time
+const IWDG_TIMEOUT_MS: u32 = 500;
+/// The tick must refresh the WWDG between these, measured from the last refresh
+const WWDG_TIMEOUT_US: u32 = 40_000;
+const WWDG_EARLIEST_US: u32 = 10_000;
+
+/// Feeds the IWDG once all tasks have checked in
+struct Supervisor {
+    tasks: TaskWatchdog,
+    iwdg: Iwdg,
+    timer: Timer<stm32::TIM3>,
+    green: PB0<Output<PushPull>>,
+    /// Set while tasks are overdue, reports each stall once
+    stalled: bool,
+}
+
+/// Fixed rate tick guarded by the WWDG
+struct Tick {
+    wwdg: Wwdg,
+    timer: Timer<stm32::TIM4>,
+    task: TaskId,
+}
+
+static SUPERVISOR: Mutex<RefCell<Option<Supervisor>>> = Mutex::new(RefCell::new(None));
+static TICK: Mutex<RefCell<Option<Tick>>> = Mutex::new(RefCell::new(None));
+
+fn itm() -> &'static mut peripheral::itm::Stim {
+    unsafe { &mut (*peripheral::ITM::ptr()).stim[0] }
+}
+
+fn check_in(task: TaskId) {
+    free(|cs| {
+        if let Some(ref mut supervisor) = SUPERVISOR.borrow(cs).borrow_mut().deref_mut() {
+            supervisor.tasks.check_in(task);
+        }
+    });
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+
+    // Find out why we are here before anything else
+    let cause = watchdog::reset_cause(&dp.RCC);
+    iprintln!(itm(), "Reset cause: {:?}", cause);
+    // Stop both watchdogs while the core is halted by the debugger
+    dp.DBGMCU
+        .apb1_fz
+        .modify(|_, w| w.dbg_iwdg_stop().set_bit().dbg_wwdg_stop().set_bit());
+
+    let rcc = dp.RCC.constrain();
+    let clocks = rcc.cfgr.sysclk(16.mhz()).freeze();
+
+    // Green blinks while supervised, red stays on after a watchdog reset
+    let gpiob = dp.GPIOB.split();
+    let green = gpiob.pb0.into_push_pull_output();
+    let mut blue = gpiob.pb7.into_push_pull_output();
+    let mut red = gpiob.pb14.into_push_pull_output();
+    if let ResetCause::IndependentWatchdog | ResetCause::WindowWatchdog = cause {
+        red.set_high().unwrap();
+    }
+
+    // Set up sonar, `read` waits forever without a sensor
+    let gpioc = dp.GPIOC.split();
+    let pin = gpioc.pc10.into_pull_down_input();
+    let mut sonar = MaxSonar::new(dp.TIM2, Model::LV, pin, clocks.sysclk());
+
+    let mut tasks = TaskWatchdog::new();
+    let sonar_task = tasks.register("sonar").unwrap();
+    let tick_task = tasks.register("tick").unwrap();
+
+    // Supervisor runs at 10 Hz, the tick at 50 Hz
+    let mut supervisor_timer = Timer::tim3(dp.TIM3, 10.hz(), clocks);
+    supervisor_timer.listen(Event::TimeOut);
+    let mut tick_timer = Timer::tim4(dp.TIM4, 50.hz(), clocks);
+    tick_timer.listen(Event::TimeOut);
+
+    // Once started the IWDG can't be stopped, only a reset does
+    let iwdg = Iwdg::new(dp.IWDG, IWDG_TIMEOUT_MS);
+    iprintln!(itm(), "IWDG timeout {} ms", iwdg.timeout_ms());
+    let mut wwdg = Wwdg::new(dp.WWDG, WWDG_TIMEOUT_US, WWDG_EARLIEST_US, clocks);
+    iprintln!(
+        itm(),
+        "WWDG refresh between {} and {} us",
+        wwdg.earliest_us(),
+        wwdg.timeout_us()
+    );
+    wwdg.listen();
+
+    free(|cs| {
+        SUPERVISOR.borrow(cs).replace(Some(Supervisor {
+            tasks,
+            iwdg,
+            timer: supervisor_timer,
+            green,
+            stalled: false,
+        }));
+        TICK.borrow(cs).replace(Some(Tick {
+            wwdg,
+            timer: tick_timer,
+            task: tick_task,
+        }));
+    });
+
+    // Enable interrupts
+    stm32::NVIC::unpend(stm32::Interrupt::TIM3);
+    stm32::NVIC::unpend(stm32::Interrupt::TIM4);
+    stm32::NVIC::unpend(stm32::Interrupt::WWDG);
+    unsafe {
+        stm32::NVIC::unmask(stm32::Interrupt::TIM3);
+        stm32::NVIC::unmask(stm32::Interrupt::TIM4);
+        stm32::NVIC::unmask(stm32::Interrupt::WWDG);
+    }
+
+    loop {
+        let distance = sonar.read();
+        iprintln!(itm(), "{}{}", distance, sonar.unit());
+        blue.toggle().unwrap();
+        check_in(sonar_task);
+    }
+}
+
+#[interrupt]
+fn TIM3() {
+    free(|cs| {
+        if let Some(ref mut supervisor) = SUPERVISOR.borrow(cs).borrow_mut().deref_mut() {
+            supervisor.timer.clear_interrupt(Event::TimeOut);
+            match supervisor.tasks.feed(&mut supervisor.iwdg) {
+                Ok(()) => {
+                    supervisor.stalled = false;
+                    supervisor.green.toggle().unwrap();
+                }
+                Err(_) if supervisor.stalled => {}
+                Err(missing) => {
+                    supervisor.stalled = true;
+                    for name in supervisor.tasks.names(missing) {
+                        iprintln!(itm(), "Task {} is late", name);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[interrupt]
+fn TIM4() {
+    free(|cs| {
+        if let Some(ref mut tick) = TICK.borrow(cs).borrow_mut().deref_mut() {
+            tick.timer.clear_interrupt(Event::TimeOut);
+            tick.wwdg.feed();
+            if let Some(ref mut supervisor) = SUPERVISOR.borrow(cs).borrow_mut().deref_mut() {
+                supervisor.tasks.check_in(tick.task);
+            }
+        }
+    });
+}
+
+// Early warning, one WWDG tick before the reset
+#[interrupt]
+fn WWDG() {
+    free(|cs| {
+        if let Some(ref mut tick) = TICK.borrow(cs).borrow_mut().deref_mut() {
+            tick.wwdg.clear_interrupt();
+        }
+    });
+    iprintln!(itm(), "WWDG about to reset");
+}
+
+mod watchdog {
+    use stm32f4xx_hal::hal::watchdog::Watchdog;
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{IWDG, RCC, WWDG};
+
+    /// Nominal LSI frequency. It varies from 17 to 47 kHz between parts,
+    /// so IWDG timeouts are only good to about a factor of two.
+    const LSI_HZ: u32 = 32_000;
+
+    const IWDG_KEY_FEED: u32 = 0xAAAA;
+    const IWDG_KEY_UNLOCK: u32 = 0x5555;
+    const IWDG_KEY_START: u32 = 0xCCCC;
+    const IWDG_MAX_RELOAD: u32 = 0xFFF;
+
+    /// The WWDG resets when its counter drops below this
+    const WWDG_COUNTER_MIN: u32 = 0x40;
+    /// Ticks from a refresh to the reset at most
+    const WWDG_MAX_TICKS: u32 = 0x40;
+    const WWDG_CR_WDGA: u32 = 1 << 7;
+    const WWDG_CFR_EWI: u32 = 1 << 9;
+
+    /// RCC_CSR reset flags
+    const CSR_RMVF: u32 = 1 << 24;
+    const CSR_BORRSTF: u32 = 1 << 25;
+    const CSR_PINRSTF: u32 = 1 << 26;
+    const CSR_PORRSTF: u32 = 1 << 27;
+    const CSR_SFTRSTF: u32 = 1 << 28;
+    const CSR_IWDGRSTF: u32 = 1 << 29;
+    const CSR_WWDGRSTF: u32 = 1 << 30;
+    const CSR_LPWRRSTF: u32 = 1 << 31;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ResetCause {
+        LowPower,
+        WindowWatchdog,
+        IndependentWatchdog,
+        Software,
+        PowerOn,
+        Brownout,
+        /// NRST pin, e.g. the reset button or the debugger
+        Pin,
+        Unknown,
+    }
+
+    impl ResetCause {
+        /// Most specific cause in the RCC_CSR flags. Every internal reset
+        /// also pulls NRST, so the pin flag comes last.
+        pub fn from_csr(csr: u32) -> Self {
+            if csr & CSR_LPWRRSTF != 0 {
+                ResetCause::LowPower
+            } else if csr & CSR_WWDGRSTF != 0 {
+                ResetCause::WindowWatchdog
+            } else if csr & CSR_IWDGRSTF != 0 {
+                ResetCause::IndependentWatchdog
+            } else if csr & CSR_SFTRSTF != 0 {
+                ResetCause::Software
+            } else if csr & CSR_PORRSTF != 0 {
+                // Power on sets the brownout flag too
+                ResetCause::PowerOn
+            } else if csr & CSR_BORRSTF != 0 {
+                ResetCause::Brownout
+            } else if csr & CSR_PINRSTF != 0 {
+                ResetCause::Pin
+            } else {
+                ResetCause::Unknown
+            }
+        }
+    }
+
+    /// Reads the cause of the last reset and clears the flags,
+    /// so the next reset reports only its own
+    pub fn reset_cause(rcc: &RCC) -> ResetCause {
+        let csr = rcc.csr.read().bits();
+        rcc.csr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CSR_RMVF) });
+        ResetCause::from_csr(csr)
+    }
+
+    /// Prescaler register value and reload for `timeout_ms`, clamped to 32.768 s
+    pub fn iwdg_config(timeout_ms: u32) -> (u32, u32) {
+        let ticks = timeout_ms as u64 * LSI_HZ as u64 / 1_000;
+        for pr in 0..=6 {
+            let divider = 4u64 << pr;
+            let reload = (ticks + divider - 1) / divider;
+            if reload <= IWDG_MAX_RELOAD as u64 + 1 {
+                return (pr, reload.max(1) as u32 - 1);
+            }
+        }
+        (6, IWDG_MAX_RELOAD)
+    }
+
+    /// Independent watchdog, clocked by the LSI
+    pub struct Iwdg {
+        iwdg: IWDG,
+        timeout_ms: u32,
+    }
+
+    impl Iwdg {
+        /// Starts the watchdog, it runs until the next reset
+        pub fn new(iwdg: IWDG, timeout_ms: u32) -> Self {
+            let (pr, rlr) = iwdg_config(timeout_ms);
+            // Starting it also starts the LSI
+            iwdg.kr.write(|w| unsafe { w.bits(IWDG_KEY_START) });
+            iwdg.kr.write(|w| unsafe { w.bits(IWDG_KEY_UNLOCK) });
+            iwdg.pr.write(|w| unsafe { w.bits(pr) });
+            iwdg.rlr.write(|w| unsafe { w.bits(rlr) });
+            // PVU and RVU clear once the values reach the LSI domain
+            while iwdg.sr.read().bits() != 0 {}
+            iwdg.kr.write(|w| unsafe { w.bits(IWDG_KEY_FEED) });
+            Iwdg {
+                iwdg,
+                timeout_ms: ((rlr + 1) as u64 * (4 << pr) * 1_000 / LSI_HZ as u64) as u32,
+            }
+        }
+
+        /// Timeout at the nominal LSI frequency
+        pub fn timeout_ms(&self) -> u32 {
+            self.timeout_ms
+        }
+    }
+
+    impl Watchdog for Iwdg {
+        fn feed(&mut self) {
+            self.iwdg.kr.write(|w| unsafe { w.bits(IWDG_KEY_FEED) });
+        }
+    }
+
+    /// Timer base, counter and window values for the WWDG
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct WwdgConfig {
+        /// WDGTB, the divider is 4096 << `timer_base`
+        pub timer_base: u32,
+        pub counter: u32,
+        pub window: u32,
+        /// One counter tick in ns
+        pub tick_ns: u32,
+    }
+
+    impl WwdgConfig {
+        /// Finest timer base that reaches `timeout_us`. Refreshing before `earliest_us`
+        /// since the last refresh resets, 0 allows refreshing at any time.
+        pub fn new(pclk1: u32, timeout_us: u32, earliest_us: u32) -> Self {
+            let ticks_in = |us: u32, timer_base: u32| {
+                us as u64 * pclk1 as u64 / ((4096u64 << timer_base) * 1_000_000)
+            };
+            let timer_base = (0..3)
+                .find(|&tb| ticks_in(timeout_us, tb) <= WWDG_MAX_TICKS as u64)
+                .unwrap_or(3);
+            let timeout = (ticks_in(timeout_us, timer_base) as u32)
+                .max(1)
+                .min(WWDG_MAX_TICKS);
+            // Round up, never allow a refresh before `earliest_us`
+            let tick_ns = ((4096u64 << timer_base) * 1_000_000_000 / pclk1 as u64) as u32;
+            let earliest = (earliest_us as u64 * 1_000 + tick_ns as u64 - 1) / tick_ns as u64;
+            let counter = WWDG_COUNTER_MIN - 1 + timeout;
+            let window = counter - (earliest as u32).min(timeout - 1);
+            WwdgConfig {
+                timer_base,
+                counter,
+                window,
+                tick_ns,
+            }
+        }
+
+        pub fn timeout_us(&self) -> u32 {
+            (self.counter - WWDG_COUNTER_MIN + 1) * self.tick_ns / 1_000
+        }
+
+        pub fn earliest_us(&self) -> u32 {
+            (self.counter - self.window) * self.tick_ns / 1_000
+        }
+    }
+
+    /// Window watchdog, clocked by PCLK1. Catches refreshes that come too late
+    /// and, with a window, too early.
+    pub struct Wwdg {
+        wwdg: WWDG,
+        config: WwdgConfig,
+    }
+
+    impl Wwdg {
+        /// Starts the watchdog, it runs until the next reset.
+        /// The longest timeout is 131 ms at 16 MHz PCLK1, 43 ms at 48 MHz.
+        pub fn new(wwdg: WWDG, timeout_us: u32, earliest_us: u32, clocks: Clocks) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr.modify(|_, w| w.wwdgen().set_bit());
+
+            let config = WwdgConfig::new(clocks.pclk1().0, timeout_us, earliest_us);
+            wwdg.cfr
+                .write(|w| unsafe { w.bits(config.timer_base << 7 | config.window) });
+            wwdg.cr
+                .write(|w| unsafe { w.bits(WWDG_CR_WDGA | config.counter) });
+            Wwdg { wwdg, config }
+        }
+
+        pub fn timeout_us(&self) -> u32 {
+            self.config.timeout_us()
+        }
+
+        pub fn earliest_us(&self) -> u32 {
+            self.config.earliest_us()
+        }
+
+        /// Early wakeup interrupt when the counter reaches 0x40, one tick before
+        /// the reset. Only a reset turns it off again.
+        pub fn listen(&mut self) {
+            self.wwdg
+                .cfr
+                .modify(|r, w| unsafe { w.bits(r.bits() | WWDG_CFR_EWI) });
+        }
+
+        pub fn clear_interrupt(&mut self) {
+            self.wwdg.sr.write(|w| unsafe { w.bits(0) });
+        }
+    }
+
+    impl Watchdog for Wwdg {
+        fn feed(&mut self) {
+            self.wwdg
+                .cr
+                .write(|w| unsafe { w.bits(WWDG_CR_WDGA | self.config.counter) });
+        }
+    }
+
+    /// Tasks that can be registered
+    pub const CAPACITY: usize = 8;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TaskId(u8);
+
+    /// Feeds a hardware watchdog only after every registered task has
+    /// checked in, so one stuck task is enough to reset
+    pub struct TaskWatchdog {
+        names: [Option<&'static str>; CAPACITY],
+        /// Bit per registered task
+        registered: u32,
+        /// Bit per task checked in since the last feed
+        checked: u32,
+    }
+
+    impl TaskWatchdog {
+        pub fn new() -> Self {
+            TaskWatchdog {
+                names: [None; CAPACITY],
+                registered: 0,
+                checked: 0,
+            }
+        }
+
+        /// `None` when all slots are taken
+        pub fn register(&mut self, name: &'static str) -> Option<TaskId> {
+            let slot = self.names.iter().position(Option::is_none)?;
+            self.names[slot] = Some(name);
+            self.registered |= 1 << slot;
+            Some(TaskId(slot as u8))
+        }
+
+        pub fn check_in(&mut self, id: TaskId) {
+            self.checked |= (1 << id.0) & self.registered;
+        }
+
+        /// Tasks not checked in since the last feed, bit per task
+        pub fn missing(&self) -> u32 {
+            self.registered & !self.checked
+        }
+
+        /// Feeds `watchdog` and starts a new round if every task has checked in.
+        /// Otherwise returns the missing tasks.
+        pub fn feed<W: Watchdog>(&mut self, watchdog: &mut W) -> Result<(), u32> {
+            match self.missing() {
+                0 => {
+                    watchdog.feed();
+                    self.checked = 0;
+                    Ok(())
+                }
+                missing => Err(missing),
+            }
+        }
+
+        /// Names of the tasks in `mask`
+        pub fn names(&self, mask: u32) -> impl Iterator<Item = &'static str> + '_ {
+            self.names
+                .iter()
+                .enumerate()
+                .filter(move |&(slot, _)| mask & (1 << slot) != 0)
+                .filter_map(|(_, name)| *name)
+        }
+    }
+
+    impl Default for TaskWatchdog {
+        fn default() -> Self {
+            TaskWatchdog::new()
+        }
+    }
+}
+
+mod maxsonar {
+    use stm32f4xx_hal::hal::digital::v2::InputPin;
+    use stm32f4xx_hal::stm32::{RCC, TIM2};
+    use stm32f4xx_hal::time::Hertz;
+
+    pub struct MaxSonar<T> {
+        timer: TIM2,
+        model: Model,
+        pin: T,
+    }
+
+    impl<T, E> MaxSonar<T>
+    where
+        T: InputPin<Error = E>,
+        E: core::fmt::Debug,
+    {
+        pub fn new(timer: TIM2, model: Model, pin: T, sysclk: Hertz) -> Self {
+            // Configure timer for 1Mhz
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr.modify(|_, w| w.tim2en().set_bit());
+            let psc = (sysclk.0 / 1_000_000) as u16;
+            timer.psc.write(|w| w.psc().bits(psc - 1));
+            timer.egr.write(|w| w.ug().set_bit());
+            // Start MaxSonar
+            let mut sonar = MaxSonar { timer, model, pin };
+            sonar.start();
+            sonar
+        }
+        /// Calculates the distance
+        pub fn read(&mut self) -> u32 {
+            while self.pin.is_low().unwrap() {}
+            self.timer.cnt.reset();
+            while self.pin.is_high().unwrap() {}
+            self.timer.cnt.read().bits() / self.model.factor()
+        }
+        /// Returns the unit for the model
+        pub fn unit(&self) -> &'static str {
+            self.model.unit()
+        }
+        /// Starts the timer
+        fn start(&mut self) {
+            self.timer.cnt.reset();
+            self.timer.cr1.write(|w| w.cen().set_bit());
+        }
+    }
+
+    /// Maxbotix Ultra Sensor Models
+    #[derive(Debug, Clone, Copy)]
+    pub enum Model {
+        LV,
+        XL,
+        HR,
+    }
+
+    impl Model {
+        /// scale factor
+        fn factor(self) -> u32 {
+            match self {
+                Model::LV => 147,
+                Model::XL => 58,
+                Model::HR => 1,
+            }
+        }
+        /// unit
+        fn unit(self) -> &'static str {
+            match self {
+                Model::LV => "\"",
+                Model::XL => "cm",
+                Model::HR => "mm",
+            }
+        }
+    }
+}
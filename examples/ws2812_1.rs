@@ -0,0 +1,265 @@
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use cortex_m::singleton;
+use cortex_m_rt::entry;
+use encode::Rgb;
+use stm32f4xx_hal::{prelude::*, stm32};
+use ws2812::{Ws2812Pwm, Ws2812Spi};
+
+#[path = "shared/ws2812_encode.rs"]
+mod encode;
+
+/// LEDs per strip
+const LEDS: usize = 300;
+const SPI_LEN: usize = encode::spi_len(LEDS);
+const PWM_LEN: usize = encode::pwm_len(LEDS);
+
+#[entry]
+fn main() -> ! {
+    let dp = stm32::Peripherals::take().unwrap();
+    let rcc = dp.RCC.constrain();
+    // 48 MHz / 16 gives the 3 MHz SPI clock the encoding expects
+    let clocks = rcc.cfgr.sysclk(48.mhz()).pclk2(48.mhz()).freeze();
+    let gpioa = dp.GPIOA.split();
+
+    // Strip 1 data on PA7 (SPI1 MOSI), strip 2 data on PA8 (TIM1 CH1).
+    // Both strips are fed by DMA2, the CPU only encodes.
+    let pa7 = gpioa.pa7.into_alternate_af5();
+    let spi_buffer = singleton!(: [u8; SPI_LEN] = [0; SPI_LEN]).unwrap();
+    let mut strip1 = Ws2812Spi::spi1(dp.SPI1, pa7, spi_buffer, clocks);
+
+    let pa8 = gpioa.pa8.into_alternate_af1();
+    let pwm_buffer = singleton!(: [u16; PWM_LEN] = [0; PWM_LEN]).unwrap();
+    let mut strip2 = Ws2812Pwm::tim1(dp.TIM1, pa8, pwm_buffer, clocks);
+
+    let dma = dp.DMA2;
+    let mut offset: u8 = 0;
+    loop {
+        // Rainbow chase, opposite directions on the two strips
+        strip1.write(&dma, (0..LEDS).map(|i| rainbow(i, offset)));
+        strip2.write(&dma, (0..LEDS).map(|i| rainbow(LEDS - 1 - i, offset)));
+        offset = offset.wrapping_add(2);
+        // About 50 frames per second
+        cortex_m::asm::delay(48_000_000 / 50);
+    }
+}
+
+/// Hue wheel along the strip, at 1/8 brightness to keep the current down
+fn rainbow(index: usize, offset: u8) -> Rgb {
+    let hue = ((index * 256 / LEDS) as u8).wrapping_add(offset).min(254);
+    let t = (hue % 85) * 3;
+    let (r, g, b) = match hue / 85 {
+        0 => (255 - t, t, 0),
+        1 => (0, 255 - t, t),
+        _ => (t, 0, 255 - t),
+    };
+    Rgb::new(r >> 3, g >> 3, b >> 3)
+}
+
+mod ws2812 {
+    use crate::encode::{self, PwmTiming, Rgb};
+    use stm32f4xx_hal::gpio::{gpioa::PA7, gpioa::PA8, Alternate, AF1, AF5};
+    use stm32f4xx_hal::rcc::Clocks;
+    use stm32f4xx_hal::stm32::{DMA2, RCC, SPI1, TIM1};
+
+    /// Strip on SPI1 MOSI, DMA2 Stream3 Channel3
+    pub struct Ws2812Spi {
+        spi: SPI1,
+        _pin: PA7<Alternate<AF5>>,
+        buffer: &'static mut [u8],
+    }
+
+    impl Ws2812Spi {
+        /// PCLK2 must be 48 MHz for the 3 MHz bit clock
+        pub fn spi1(
+            spi: SPI1,
+            pin: PA7<Alternate<AF5>>,
+            buffer: &'static mut [u8],
+            clocks: Clocks,
+        ) -> Self {
+            assert!(clocks.pclk2().0 == encode::SPI_HZ * 16);
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb2enr.modify(|_, w| w.spi1en().set_bit());
+            rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+
+            // Transmit only master, mode 0, PCLK2 / 16
+            spi.cr1.write(|w| {
+                w.bidimode()
+                    .set_bit()
+                    .bidioe()
+                    .set_bit()
+                    .ssm()
+                    .set_bit()
+                    .ssi()
+                    .set_bit()
+                    .mstr()
+                    .set_bit()
+                    .br()
+                    .div16()
+            });
+            spi.cr2.write(|w| w.txdmaen().set_bit());
+            spi.cr1.modify(|_, w| w.spe().set_bit());
+            Ws2812Spi {
+                spi,
+                _pin: pin,
+                buffer,
+            }
+        }
+
+        /// True while the previous frame is being sent
+        pub fn is_busy(&self, dma: &DMA2) -> bool {
+            dma.st[3].cr.read().en().bit_is_set()
+        }
+
+        /// Waits for the previous frame, then encodes and sends `pixels`
+        pub fn write<I>(&mut self, dma: &DMA2, pixels: I)
+        where
+            I: IntoIterator<Item = Rgb>,
+        {
+            while self.is_busy(dma) {}
+            encode::spi(pixels, self.buffer);
+
+            let stream = &dma.st[3];
+            dma.lifcr.write(|w| {
+                w.ctcif3()
+                    .set_bit()
+                    .chtif3()
+                    .set_bit()
+                    .cteif3()
+                    .set_bit()
+                    .cdmeif3()
+                    .set_bit()
+                    .cfeif3()
+                    .set_bit()
+            });
+            stream
+                .par
+                .write(|w| unsafe { w.bits(&self.spi.dr as *const _ as u32) });
+            stream
+                .m0ar
+                .write(|w| unsafe { w.bits(self.buffer.as_ptr() as u32) });
+            stream
+                .ndtr
+                .write(|w| unsafe { w.bits(self.buffer.len() as u32) });
+            // Channel 3, byte to byte, memory to peripheral, one shot
+            stream.cr.write(|w| unsafe {
+                w.chsel()
+                    .bits(3)
+                    .msize()
+                    .bits(0b00)
+                    .psize()
+                    .bits(0b00)
+                    .minc()
+                    .set_bit()
+                    .dir()
+                    .bits(0b01)
+                    .pl()
+                    .bits(0b10)
+            });
+            stream.cr.modify(|_, w| w.en().set_bit());
+        }
+    }
+
+    /// Strip on TIM1 CH1. DMA2 Stream5 Channel6 loads CCR1 on every update.
+    pub struct Ws2812Pwm {
+        tim: TIM1,
+        _pin: PA8<Alternate<AF1>>,
+        buffer: &'static mut [u16],
+        timing: PwmTiming,
+    }
+
+    impl Ws2812Pwm {
+        pub fn tim1(
+            tim: TIM1,
+            pin: PA8<Alternate<AF1>>,
+            buffer: &'static mut [u16],
+            clocks: Clocks,
+        ) -> Self {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb2enr.modify(|_, w| w.tim1en().set_bit());
+            rcc.ahb1enr.modify(|_, w| w.dma2en().set_bit());
+
+            let clock = if clocks.ppre2() == 1 {
+                clocks.pclk2().0
+            } else {
+                clocks.pclk2().0 * 2
+            };
+            let timing = PwmTiming::new(clock);
+
+            tim.psc.write(|w| w.psc().bits(0));
+            tim.arr
+                .write(|w| unsafe { w.bits(timing.period as u32 - 1) });
+            // PWM mode 1 with preload, so each DMA write applies to the next bit
+            tim.ccmr1_output()
+                .modify(|_, w| w.oc1pe().set_bit().oc1m().pwm_mode1());
+            tim.ccr1.write(|w| unsafe { w.bits(0) });
+            tim.ccer.modify(|_, w| w.cc1e().set_bit());
+            tim.bdtr.modify(|_, w| w.moe().set_bit());
+            tim.dier.modify(|_, w| w.ude().set_bit());
+            tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+            Ws2812Pwm {
+                tim,
+                _pin: pin,
+                buffer,
+                timing,
+            }
+        }
+
+        /// True while the previous frame is being sent
+        pub fn is_busy(&self, dma: &DMA2) -> bool {
+            dma.st[5].cr.read().en().bit_is_set()
+        }
+
+        /// Waits for the previous frame, then encodes and sends `pixels`.
+        /// The buffer ends with zero duty, so the line stays low afterwards.
+        pub fn write<I>(&mut self, dma: &DMA2, pixels: I)
+        where
+            I: IntoIterator<Item = Rgb>,
+        {
+            while self.is_busy(dma) {}
+            encode::pwm(pixels, self.buffer, self.timing);
+
+            let stream = &dma.st[5];
+            dma.hifcr.write(|w| {
+                w.ctcif5()
+                    .set_bit()
+                    .chtif5()
+                    .set_bit()
+                    .cteif5()
+                    .set_bit()
+                    .cdmeif5()
+                    .set_bit()
+                    .cfeif5()
+                    .set_bit()
+            });
+            stream
+                .par
+                .write(|w| unsafe { w.bits(&self.tim.ccr1 as *const _ as u32) });
+            stream
+                .m0ar
+                .write(|w| unsafe { w.bits(self.buffer.as_ptr() as u32) });
+            stream
+                .ndtr
+                .write(|w| unsafe { w.bits(self.buffer.len() as u32) });
+            // Channel 6, half-word to half-word, memory to peripheral, one shot
+            stream.cr.write(|w| unsafe {
+                w.chsel()
+                    .bits(6)
+                    .msize()
+                    .bits(0b01)
+                    .psize()
+                    .bits(0b01)
+                    .minc()
+                    .set_bit()
+                    .dir()
+                    .bits(0b01)
+                    .pl()
+                    .bits(0b10)
+            });
+            stream.cr.modify(|_, w| w.en().set_bit());
+        }
+    }
+}
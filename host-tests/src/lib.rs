@@ -0,0 +1,35 @@
+//! The hardware independent modules of the examples, built for the host so
+//! their tests can run there. The examples include the same files with `#[path]`.
+
+#[path = "../../examples/shared/timing.rs"]
+pub mod timing;
+
+#[path = "../../examples/shared/curve.rs"]
+pub mod curve;
+
+#[path = "../../examples/shared/filter.rs"]
+pub mod filter;
+
+#[path = "../../examples/shared/pid.rs"]
+pub mod pid;
+
+#[path = "../../examples/shared/dead_time.rs"]
+pub mod dead_time;
+
+#[path = "../../examples/shared/motor.rs"]
+pub mod motor;
+
+#[path = "../../examples/shared/ws2812_encode.rs"]
+pub mod ws2812_encode;
+
+#[path = "../../examples/shared/gesture.rs"]
+pub mod gesture;
+
+#[path = "../../examples/shared/soft.rs"]
+pub mod soft;
+
+#[path = "../../examples/shared/frequency_range.rs"]
+pub mod frequency_range;
+
+#[path = "../../examples/shared/led_effects.rs"]
+pub mod led_effects;
@@ -0,0 +1,253 @@
+//! Decodes the binary ADC capture stream of `examples/adc_capture_1.rs` into CSV.
+//!
+//! ``` console
+//! $ stty -F /dev/ttyACM0 921600 raw
+//! $ cargo run --target <host triple> -- /dev/ttyACM0 > capture.csv
+//! ```
+//!
+//! Reads stdin when no path is given. The timestamps are sample count times the
+//! nominal sample period, see `examples/shared/capture.rs` for the frame format.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+
+// The encoder half is only used by the tests
+#[allow(dead_code)]
+#[path = "../../../examples/shared/capture.rs"]
+mod capture;
+
+use capture::{HEADER, SYNC};
+
+/// One decoded frame
+#[derive(Debug, PartialEq)]
+struct Frame {
+    sequence: u8,
+    timestamp_us: u32,
+    period_us: u16,
+    samples: Vec<u16>,
+}
+
+/// Outcome of looking for a frame at the start of the buffer
+enum Parse {
+    /// A complete, valid frame and its length in bytes
+    Frame(Frame, usize),
+    /// More bytes are needed
+    Incomplete,
+    /// No valid frame starts here
+    Invalid,
+}
+
+fn parse(buf: &[u8]) -> Parse {
+    if buf.len() < HEADER {
+        return Parse::Incomplete;
+    }
+    if buf[..2] != SYNC {
+        return Parse::Invalid;
+    }
+    let count = buf[3] as usize;
+    let len = HEADER + count * 2 + 1;
+    if buf.len() < len {
+        return Parse::Incomplete;
+    }
+    let checksum = buf[2..len - 1].iter().fold(0, |acc, &b| acc ^ b);
+    if checksum != buf[len - 1] {
+        return Parse::Invalid;
+    }
+    let samples = buf[HEADER..len - 1]
+        .chunks(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    let frame = Frame {
+        sequence: buf[2],
+        timestamp_us: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        period_us: u16::from_le_bytes([buf[8], buf[9]]),
+        samples,
+    };
+    Parse::Frame(frame, len)
+}
+
+/// Writes frames as CSV rows and keeps track of the timeline
+struct Writer<W: Write> {
+    out: W,
+    last_sequence: Option<u8>,
+    last_timestamp: u32,
+    /// Extends the 32 bit device timestamp to 64 bits
+    epoch: u64,
+}
+
+impl<W: Write> Writer<W> {
+    fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "timestamp_us,sample")?;
+        Ok(Writer {
+            out,
+            last_sequence: None,
+            last_timestamp: 0,
+            epoch: 0,
+        })
+    }
+
+    fn write(&mut self, frame: &Frame) -> io::Result<()> {
+        if let Some(last) = self.last_sequence {
+            let expected = last.wrapping_add(1);
+            if frame.sequence != expected {
+                eprintln!("gap: expected frame {}, got {}", expected, frame.sequence);
+            }
+            if frame.timestamp_us < self.last_timestamp {
+                self.epoch += 1 << 32;
+            }
+        }
+        self.last_sequence = Some(frame.sequence);
+        self.last_timestamp = frame.timestamp_us;
+
+        let start = self.epoch + frame.timestamp_us as u64;
+        for (i, sample) in frame.samples.iter().enumerate() {
+            let timestamp = start + i as u64 * frame.period_us as u64;
+            writeln!(self.out, "{},{}", timestamp, sample)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut input: Box<dyn Read> = match env::args().nth(1) {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+    let stdout = io::stdout();
+    let mut writer = Writer::new(BufWriter::new(stdout.lock()))?;
+
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let mut skipped = 0usize;
+    loop {
+        let n = input.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut pos = 0;
+        loop {
+            match parse(&buf[pos..]) {
+                Parse::Frame(frame, len) => {
+                    if skipped > 0 {
+                        eprintln!("resync: skipped {} bytes", skipped);
+                        skipped = 0;
+                    }
+                    writer.write(&frame)?;
+                    pos += len;
+                }
+                Parse::Incomplete => break,
+                Parse::Invalid => {
+                    // Look for the next sync
+                    pos += 1;
+                    skipped += 1;
+                }
+            }
+        }
+        buf.drain(..pos);
+        writer.out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capture::{Block, FRAME_MAX, SAMPLES};
+
+    /// `blocks` full frames from the firmware encoder, the samples count up from 0
+    fn encode(blocks: usize, period_us: u16) -> Vec<u8> {
+        let mut block = Block::new(period_us);
+        let mut stream = Vec::new();
+        let mut value = 0u16;
+        for _ in 0..blocks {
+            while !block.push(value) {
+                value = value.wrapping_add(1);
+            }
+            value = value.wrapping_add(1);
+            let mut buf = [0u8; FRAME_MAX];
+            let len = block.encode(&mut buf);
+            stream.extend_from_slice(&buf[..len]);
+            block.next();
+        }
+        stream
+    }
+
+    fn parse_all(mut buf: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        loop {
+            match parse(buf) {
+                Parse::Frame(frame, len) => {
+                    frames.push(frame);
+                    buf = &buf[len..];
+                }
+                Parse::Incomplete => return frames,
+                Parse::Invalid => buf = &buf[1..],
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let stream = encode(3, 500);
+        assert_eq!(stream.len(), 3 * FRAME_MAX);
+        let frames = parse_all(&stream);
+        assert_eq!(frames.len(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            let first = (i * SAMPLES) as u16;
+            assert_eq!(
+                *frame,
+                Frame {
+                    sequence: i as u8,
+                    timestamp_us: (i * SAMPLES * 500) as u32,
+                    period_us: 500,
+                    samples: (first..first + SAMPLES as u16).collect(),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more_bytes() {
+        let stream = encode(1, 500);
+        for len in 0..stream.len() {
+            assert!(matches!(parse(&stream[..len]), Parse::Incomplete));
+        }
+        assert!(matches!(parse(&stream), Parse::Frame(_, len) if len == FRAME_MAX));
+    }
+
+    #[test]
+    fn corrupt_frames_are_skipped() {
+        let mut stream = encode(3, 500);
+        // Break the checksum of the middle frame, and put noise in front
+        stream[FRAME_MAX + HEADER] ^= 0x01;
+        stream.splice(0..0, [0x00, 0xA5, 0x13].iter().cloned());
+        let frames = parse_all(&stream);
+        let sequences: Vec<u8> = frames.iter().map(|f| f.sequence).collect();
+        assert_eq!(sequences, [0, 2]);
+    }
+
+    #[test]
+    fn timestamps_continue_across_the_32_bit_wrap() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        for (sequence, timestamp_us) in [(0u8, 4_294_966_296), (1, 24_000)].iter() {
+            let frame = Frame {
+                sequence: *sequence,
+                timestamp_us: *timestamp_us,
+                period_us: 500,
+                samples: vec![1, 2],
+            };
+            writer.write(&frame).unwrap();
+        }
+        let csv = String::from_utf8(writer.out).unwrap();
+        let wrapped = (1u64 << 32) + 24_000;
+        let expected = format!(
+            "timestamp_us,sample\n4294966296,1\n4294966796,2\n{},1\n{},2\n",
+            wrapped,
+            wrapped + 500
+        );
+        assert_eq!(csv, expected);
+    }
+}